@@ -0,0 +1,87 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha1::{Digest, Sha1};
+
+const VERIFIED_CACHE_MAX:usize = 1024;
+
+//htpasswd file, support bcrypt($2y$), sha crypt($5$ $6$), md5 crypt($1$) and {SHA}.
+//apache $apr1$ is not supported, use `htpasswd -B` to generate bcrypt password.
+#[derive(Debug,Default)]
+pub struct Htpasswd{
+    users:HashMap<String,String>,
+    //bcrypt is slow, so cache the digest of the credentials that have been verified
+    verified:Mutex<HashSet<[u8;20]>>,
+}
+
+impl Htpasswd{
+    //return the htpasswd and the invalid lines
+    pub fn parse(content:&str)->(Self,Vec<String>){
+        let mut users = HashMap::new();
+        let mut invalid = vec![];
+        for line in content.lines().map(|x|x.trim()){
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+            match line.split_once(':') {
+                Some((user,hash)) if !user.is_empty() && !hash.starts_with("$apr1$") => {
+                    users.insert(user.to_string(),hash.to_string());
+                }
+                _ => invalid.push(line.split(':').next().unwrap_or_default().to_string()),
+            }
+        }
+        (Self{users,verified:Mutex::default()},invalid)
+    }
+    pub fn verify(&self,user:&str,password:&str)->bool{
+        let hash = if let Some(s) = self.users.get(user){ s }else{ return false };
+        let digest:[u8;20] = Sha1::new()
+            .chain_update(user).chain_update([0]).chain_update(password).chain_update([0]).chain_update(hash)
+            .finalize().into();
+        if self.verified.lock().unwrap().contains(&digest) {
+            return true
+        }
+        let ok = if let Some(sha) = hash.strip_prefix("{SHA}"){
+            constant_time_eq(STANDARD.encode(Sha1::digest(password)).as_bytes(),sha.as_bytes())
+        }else{
+            pwhash::unix::verify(password,hash)
+        };
+        if ok {
+            let mut verified = self.verified.lock().unwrap();
+            if verified.len() >= VERIFIED_CACHE_MAX {
+                verified.clear();
+            }
+            verified.insert(digest);
+        }
+        ok
+    }
+}
+
+//the time does not depend on the position of the first different byte
+fn constant_time_eq(a:&[u8],b:&[u8])->bool{
+    a.len() == b.len() && a.iter().zip(b).fold(0u8,|acc,(x,y)|acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test{
+    use crate::infra::htpasswd::Htpasswd;
+
+    #[test]
+    fn test_htpasswd(){
+        let content = "
+# test users
+bob:$2y$05$xsywrWofbozRujNWMJt.aO9MFfcZiTMLmVThDc0ltoNfZBrUjWAGq
+alice:{SHA}qUqP5cyxm6YcTAhz05Hph5gvu9M=
+tom:$apr1$ZjTqBB3f$IF9gdYAGlMrs2fuINjHsz.
+jerry
+";
+        let (ht,invalid) = Htpasswd::parse(content);
+        assert_eq!(invalid,vec!["tom".to_string(),"jerry".to_string()]);
+        assert!(ht.verify("bob","123456"));
+        assert!(ht.verify("bob","123456"));
+        assert!(!ht.verify("bob","1234567"));
+        assert!(ht.verify("alice","test"));
+        assert!(!ht.verify("alice","tes"));
+        assert!(!ht.verify("tom","123456"));
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::Duration;
+use bytes::{Bytes, BytesMut};
+use pingora::connectors::http::Connector;
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::protocols::http::client::HttpSession;
+use pingora::upstreams::peer::HttpPeer;
+use url::Url;
+
+#[derive(Debug)]
+pub struct HttpResponse{
+    pub header:ResponseHeader,
+    pub body:Bytes,
+}
+
+//a simple http client for the requests sent by gateway itself, the connections are reused
+pub struct HttpClient{
+    connector:Connector,
+    timeout:Duration,
+}
+
+impl HttpClient{
+    pub fn new(timeout:Duration)->Self{
+        Self{connector:Connector::new(None),timeout}
+    }
+    async fn peer(url:&Url,timeout:Duration)->anyhow::Result<HttpPeer>{
+        let tls = match url.scheme() {
+            "http" => false,
+            "https" => true,
+            s => return Err(anyhow::anyhow!("unsupported scheme:{}",s)),
+        };
+        let host = url.host_str().ok_or_else(||anyhow::anyhow!("url[{}] has no host",url))?;
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addr = tokio::net::lookup_host((host.trim_matches(|c|c=='['||c==']'),port)).await?
+            .next().ok_or_else(||anyhow::anyhow!("resolve host[{}] failed",host))?;
+        let mut peer = HttpPeer::new(addr,tls,host.to_string());
+        peer.options.connection_timeout = Some(timeout);
+        peer.options.read_timeout = Some(timeout);
+        peer.options.write_timeout = Some(timeout);
+        Ok(peer)
+    }
+    //the path and query of url, and the Host header will be set.
+    //the response body over max_body is dropped
+    pub async fn send(&self,url:&Url,mut req:RequestHeader,body:Option<Bytes>,max_body:usize)->anyhow::Result<HttpResponse>{
+        let peer = HttpClient::peer(url,self.timeout).await?;
+        let path = match url.query() {
+            Some(q) => format!("{}?{}",url.path(),q),
+            None => url.path().to_string(),
+        };
+        req.set_uri(path.parse()?);
+        let host = match url.port() {
+            Some(p) => format!("{}:{}",url.host_str().unwrap_or_default(),p),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        req.insert_header("Host",host)?;
+        if let Some(ref b) = body{
+            req.insert_header("Content-Length",b.len().to_string())?;
+        }
+
+        let (mut session,_) = self.connector.get_http_session(&peer).await?;
+        let res = tokio::time::timeout(self.timeout,HttpClient::round_trip(&mut session,req,body,max_body)).await
+            .map_err(|_|anyhow::anyhow!("request[{}] timeout",url));
+        match res {
+            Ok(Ok((resp,done))) => {
+                if done {
+                    if let HttpSession::H1(ref mut h1) = session{
+                        h1.respect_keepalive();
+                    }
+                    self.connector.release_http_session(session,&peer,None).await;
+                }
+                Ok(resp)
+            }
+            Ok(Err(e)) | Err(e) => Err(e),
+        }
+    }
+    async fn round_trip(session:&mut HttpSession,req:RequestHeader,body:Option<Bytes>,max_body:usize)->anyhow::Result<(HttpResponse,bool)>{
+        session.write_request_header(Box::new(req)).await?;
+        if let Some(b) = body{
+            session.write_request_body(b,true).await?;
+        }
+        session.finish_request_body().await?;
+        session.read_response_header().await?;
+        let header = session.response_header().ok_or_else(||anyhow::anyhow!("no response header"))?.clone();
+        let mut buf = BytesMut::new();
+        while let Some(b) = session.read_response_body().await?{
+            if buf.len() + b.len() > max_body {
+                return Ok((HttpResponse{header,body:buf.freeze()},false))
+            }
+            buf.extend_from_slice(&b);
+        }
+        Ok((HttpResponse{header,body:buf.freeze()},true))
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::time::Duration;
+    use pingora::http::RequestHeader;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::infra::http_client::HttpClient;
+
+    #[tokio::test]
+    async fn test_http_client(){
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move{
+            let (mut stream,_) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8;1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            let req = String::from_utf8_lossy(&buf[..n]).to_string();
+            assert!(req.starts_with("GET /auth?a=1 HTTP/1.1\r\n"));
+            assert!(req.contains(format!("Host: 127.0.0.1:{}",addr.port()).as_str()));
+            stream.write_all(b"HTTP/1.1 200 OK\r\nX-User: bob\r\nContent-Length: 2\r\n\r\nok").await.unwrap();
+        });
+
+        let client = HttpClient::new(Duration::from_secs(3));
+        let url = format!("http://127.0.0.1:{}/auth?a=1",addr.port()).parse().unwrap();
+        let req = RequestHeader::build("GET",b"/",None).unwrap();
+        let resp = client.send(&url,req,None,1024).await.unwrap();
+        assert_eq!(resp.header.status.as_u16(),200);
+        assert_eq!(resp.header.headers.get("X-User").unwrap(),"bob");
+        assert_eq!(resp.body.as_ref(),b"ok");
+    }
+}
@@ -0,0 +1,105 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+//a list of cidr, single ip is treated as /32 or /128
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct CidrSet{
+    pub nets:Vec<IpNet>,
+}
+
+impl CidrSet{
+    //return the set of valid items and the invalid items
+    pub fn parse<S:AsRef<str>>(list:&[S])->(Self,Vec<String>){
+        let mut nets = vec![];
+        let mut invalid = vec![];
+        for i in list.iter(){
+            let s = i.as_ref().trim();
+            if s.is_empty() {
+                continue
+            }
+            if let Ok(n) = IpNet::from_str(s){
+                nets.push(n.trunc());
+            }else if let Ok(ip) = IpAddr::from_str(s){
+                nets.push(IpNet::from(ip));
+            }else{
+                invalid.push(s.to_string());
+            }
+        }
+        (Self{nets},invalid)
+    }
+    pub fn is_empty(&self)->bool{
+        self.nets.is_empty()
+    }
+    pub fn contains(&self,ip:&IpAddr)->bool{
+        let ip = ip.to_canonical();
+        self.nets.iter().any(|x|x.contains(&ip))
+    }
+}
+
+//find the real client ip. the header is only trusted when the peer is a trusted proxy,
+//and the addresses in it are checked from right to left, the first untrusted one is the client.
+pub fn real_client_ip(peer:IpAddr,forwarded_for:Option<&str>,trusted:&CidrSet)->IpAddr{
+    if !trusted.contains(&peer) {
+        return peer
+    }
+    let header = if let Some(s) = forwarded_for{ s }else{ return peer };
+    let mut client = peer;
+    for i in header.rsplit(',').map(|x|x.trim()){
+        let ip = match parse_forwarded_ip(i) {
+            Some(ip) => ip,
+            None => break,
+        };
+        client = ip;
+        if !trusted.contains(&ip) {
+            break
+        }
+    }
+    client
+}
+
+//support ip, ip:port and [ipv6]:port
+fn parse_forwarded_ip(s:&str)->Option<IpAddr>{
+    if let Ok(ip) = IpAddr::from_str(s){
+        return Some(ip)
+    }
+    if let Some(s) = s.strip_prefix('['){
+        let (ip,_) = s.split_once(']')?;
+        return IpAddr::from_str(ip).ok()
+    }
+    let (ip,_) = s.rsplit_once(':')?;
+    IpAddr::from_str(ip).ok()
+}
+
+#[cfg(test)]
+mod test{
+    use std::net::IpAddr;
+    use crate::infra::ip::{CidrSet, real_client_ip};
+
+    #[test]
+    fn test_cidr_set(){
+        let (set,invalid) = CidrSet::parse(&["10.0.0.0/8","192.168.1.1","fd00::/8","10.1.1"]);
+        assert_eq!(invalid,vec!["10.1.1".to_string()]);
+        assert!(set.contains(&"10.2.3.4".parse().unwrap()));
+        assert!(set.contains(&"::ffff:10.2.3.4".parse().unwrap()));
+        assert!(set.contains(&"192.168.1.1".parse().unwrap()));
+        assert!(!set.contains(&"192.168.1.2".parse().unwrap()));
+        assert!(set.contains(&"fd12::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_real_client_ip(){
+        let (trusted,_) = CidrSet::parse(&["10.0.0.0/8"]);
+        let peer:IpAddr = "10.0.0.1".parse().unwrap();
+        let ip = |s:&str|s.parse::<IpAddr>().unwrap();
+
+        assert_eq!(real_client_ip(ip("1.1.1.1"),Some("2.2.2.2"),&trusted),ip("1.1.1.1"));
+        assert_eq!(real_client_ip(peer,None,&trusted),peer);
+        assert_eq!(real_client_ip(peer,Some("3.3.3.3, 2.2.2.2, 10.0.0.2"),&trusted),ip("2.2.2.2"));
+        assert_eq!(real_client_ip(peer,Some("10.0.0.3, 10.0.0.2"),&trusted),ip("10.0.0.3"));
+        assert_eq!(real_client_ip(peer,Some("2.2.2.2:1234"),&trusted),ip("2.2.2.2"));
+        assert_eq!(real_client_ip(peer,Some("[2001:db8::1]:80"),&trusted),ip("2001:db8::1"));
+        assert_eq!(real_client_ip(peer,Some("unknown, 10.0.0.2"),&trusted),ip("10.0.0.2"));
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use serde_json::{Map, Value};
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum JwtError{
+    Malformed,
+    //no key matches the kid of token, the jwks may need to be refreshed
+    UnknownKey,
+    Invalid(String),
+}
+
+impl Display for JwtError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JwtError::Malformed => write!(f,"malformed token"),
+            JwtError::UnknownKey => write!(f,"no key matches the token"),
+            JwtError::Invalid(s) => write!(f,"{}",s),
+        }
+    }
+}
+
+struct JwksKey{
+    kid:Option<String>,
+    alg:Option<Algorithm>,
+    key:DecodingKey,
+}
+
+//the public keys to verify jwt, the keys not supported are skipped
+pub struct Jwks{
+    keys:Vec<JwksKey>,
+}
+
+impl std::fmt::Debug for Jwks{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.keys.iter().map(|x|(&x.kid,&x.alg))).finish()
+    }
+}
+
+impl Jwks{
+    //a json web key set, or a single json web key
+    pub fn parse(content:&str)->anyhow::Result<Self>{
+        let list = match serde_json::from_str::<JwkSet>(content) {
+            Ok(o) => o.keys,
+            Err(_) => vec![serde_json::from_str::<Jwk>(content)?],
+        };
+        let mut keys = vec![];
+        for jwk in list.iter(){
+            let key = match DecodingKey::from_jwk(jwk) {
+                Ok(o) => o,
+                Err(e) => {
+                    wd_log::log_warn_ln!("jwks skip key[{:?}]:{}",jwk.common.key_id,e);
+                    continue
+                }
+            };
+            let alg = jwk.common.key_algorithm.and_then(|x|Algorithm::from_str(x.to_string().as_str()).ok());
+            keys.push(JwksKey{kid:jwk.common.key_id.clone(),alg,key});
+        }
+        if keys.is_empty() {
+            return Err(anyhow::anyhow!("no supported key in jwks"))
+        }
+        Ok(Self{keys})
+    }
+    //verify the signature, exp, nbf, issuer and audience, return the claims
+    pub fn verify(&self,token:&str,issuer:&[String],audience:&[String])->Result<Map<String,Value>,JwtError>{
+        let header = jsonwebtoken::decode_header(token).map_err(|_|JwtError::Malformed)?;
+        let mut validation = Validation::new(header.alg);
+        validation.validate_nbf = true;
+        if !issuer.is_empty() {
+            validation.set_issuer(issuer);
+            validation.set_required_spec_claims(&["exp","iss"]);
+        }
+        if audience.is_empty() {
+            validation.validate_aud = false;
+        }else{
+            validation.set_audience(audience);
+            validation.required_spec_claims.insert("aud".into());
+        }
+
+        let mut result = Err(JwtError::UnknownKey);
+        for i in self.keys.iter(){
+            if header.kid.is_some() && i.kid != header.kid {
+                continue
+            }
+            if i.alg.is_some() && i.alg != Some(header.alg) {
+                continue
+            }
+            match jsonwebtoken::decode::<Map<String,Value>>(token,&i.key,&validation) {
+                Ok(o) => return Ok(o.claims),
+                //the key type does not match the alg, try next one
+                Err(e) if *e.kind() == ErrorKind::InvalidAlgorithm => continue,
+                Err(e) => {
+                    result = Err(JwtError::Invalid(jwt_error_msg(e.kind())));
+                    //only one key when kid is set
+                    if header.kid.is_some() {
+                        break
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+fn jwt_error_msg(kind:&ErrorKind)->String{
+    match kind {
+        ErrorKind::InvalidSignature => "invalid signature".into(),
+        ErrorKind::ExpiredSignature => "token expired".into(),
+        ErrorKind::ImmatureSignature => "token not yet valid".into(),
+        ErrorKind::InvalidIssuer => "invalid issuer".into(),
+        ErrorKind::InvalidAudience => "invalid audience".into(),
+        ErrorKind::MissingRequiredClaim(s) => format!("missing claim {}",s),
+        _ => "invalid token".into(),
+    }
+}
+
+//the claim as a header value, array is joined by comma
+pub fn claim_to_string(value:&Value)->Option<String>{
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Array(list) => Some(list.iter().filter_map(claim_to_string).collect::<Vec<_>>().join(",")),
+        v => Some(v.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use serde_json::json;
+    use crate::infra::jwt::{claim_to_string, Jwks, JwtError};
+
+    #[test]
+    fn test_jwks_verify(){
+        //k is base64url of "secret-key-for-test"
+        let jwks = Jwks::parse(r#"{"keys":[{"kty":"oct","kid":"k1","alg":"HS256","k":"c2VjcmV0LWtleS1mb3ItdGVzdA"}]}"#).unwrap();
+        let key = EncodingKey::from_secret(b"secret-key-for-test");
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("k1".into());
+        let now = jsonwebtoken::get_current_timestamp();
+        let sign = |claims:serde_json::Value,header:&Header|jsonwebtoken::encode(header,&claims,&key).unwrap();
+        let iss = vec!["https://idp.test".to_string()];
+        let aud = vec!["api".to_string()];
+
+        let token = sign(json!({"sub":"bob","iss":"https://idp.test","aud":"api","exp":now+60,"groups":["a","b"]}),&header);
+        let claims = jwks.verify(token.as_str(),&iss,&aud).unwrap();
+        assert_eq!(claim_to_string(&claims["sub"]).unwrap(),"bob");
+        assert_eq!(claim_to_string(&claims["groups"]).unwrap(),"a,b");
+
+        let token = sign(json!({"iss":"https://idp.test","aud":"api","exp":now-600}),&header);
+        assert_eq!(jwks.verify(token.as_str(),&iss,&aud),Err(JwtError::Invalid("token expired".into())));
+        let token = sign(json!({"iss":"https://idp.test","aud":"api","exp":now+60,"nbf":now+600}),&header);
+        assert_eq!(jwks.verify(token.as_str(),&iss,&aud),Err(JwtError::Invalid("token not yet valid".into())));
+        let token = sign(json!({"iss":"https://other","aud":"api","exp":now+60}),&header);
+        assert_eq!(jwks.verify(token.as_str(),&iss,&aud),Err(JwtError::Invalid("invalid issuer".into())));
+        let token = sign(json!({"iss":"https://idp.test","aud":"web","exp":now+60}),&header);
+        assert_eq!(jwks.verify(token.as_str(),&iss,&aud),Err(JwtError::Invalid("invalid audience".into())));
+
+        header.kid = Some("k2".into());
+        let token = sign(json!({"exp":now+60}),&header);
+        assert_eq!(jwks.verify(token.as_str(),&[],&[]),Err(JwtError::UnknownKey));
+        assert_eq!(jwks.verify("xxx",&[],&[]),Err(JwtError::Malformed));
+
+        let other = EncodingKey::from_secret(b"other");
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256),&json!({"exp":now+60}),&other).unwrap();
+        assert_eq!(jwks.verify(token.as_str(),&[],&[]),Err(JwtError::Invalid("invalid signature".into())));
+    }
+}
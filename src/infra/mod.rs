@@ -1 +1,8 @@
-pub mod url_tree;
\ No newline at end of file
+pub mod url_tree;
+pub mod rate_limit;
+pub mod ip;
+pub mod htpasswd;
+pub mod http_client;
+pub mod jwt;
+pub mod cert;
+pub mod sni;
\ No newline at end of file
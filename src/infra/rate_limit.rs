@@ -0,0 +1,166 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+//the full buckets are dropped at most once in the interval when the keys are capped
+const SWEEP_INTERVAL:Duration = Duration::from_secs(10);
+
+//rate: token per second, burst: the max tokens of a bucket
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Quota{
+    pub rate:f64,
+    pub burst:f64,
+}
+
+impl Quota{
+    pub fn new(rate:f64,burst:u64)->Self{
+        let burst = (burst as f64).max(1.0);
+        Self{rate,burst}
+    }
+}
+
+//the state of limiter, it is in local memory now, and can be replaced by a shared storage later.
+#[async_trait::async_trait]
+pub trait RateLimiter:Send+Sync{
+    //None means passed, otherwise return how long to wait
+    async fn acquire(&self,key:&str,quota:Quota)->Option<Duration>;
+}
+
+#[derive(Debug)]
+struct TokenBucket{
+    tokens:f64,
+    last:Instant,
+    quota:Quota,
+}
+
+impl TokenBucket{
+    fn new(quota:Quota,now:Instant)->Self{
+        Self{tokens:quota.burst,last:now,quota}
+    }
+    fn refill(&mut self,now:Instant){
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.quota.rate).min(self.quota.burst);
+        self.last = now;
+    }
+    fn acquire(&mut self,quota:Quota,now:Instant)->Option<Duration>{
+        if self.quota != quota {
+            self.quota = quota;
+            self.tokens = self.tokens.min(quota.burst);
+        }
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None
+        }
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / quota.rate))
+    }
+    fn is_full(&mut self,now:Instant)->bool{
+        self.refill(now);
+        self.tokens >= self.quota.burst
+    }
+}
+
+#[derive(Default)]
+struct Buckets{
+    map:HashMap<String,TokenBucket>,
+    //the keys in the inserted order, the oldest is evicted first
+    order:VecDeque<String>,
+    swept:Option<Instant>,
+}
+
+impl Buckets{
+    //a full bucket is the same as a new one, so it can be dropped
+    fn sweep(&mut self,now:Instant){
+        self.map.retain(|_,b|!b.is_full(now));
+        let map = &self.map;
+        self.order.retain(|k|map.contains_key(k));
+        self.swept = Some(now);
+    }
+    fn insert(&mut self,key:&str,bucket:TokenBucket,max_keys:usize,now:Instant){
+        if self.map.len() >= max_keys && self.swept.map(|x|now.saturating_duration_since(x) >= SWEEP_INTERVAL).unwrap_or(true) {
+            self.sweep(now);
+        }
+        //the keys are capped, the oldest one is evicted in O(1)
+        while self.map.len() >= max_keys.max(1) {
+            match self.order.pop_front() {
+                Some(k) => { self.map.remove(&k); }
+                None => break,
+            }
+        }
+        self.order.push_back(key.to_string());
+        self.map.insert(key.to_string(),bucket);
+    }
+}
+
+pub struct LocalRateLimiter{
+    buckets:Mutex<Buckets>,
+    max_keys:usize,
+}
+
+impl Default for LocalRateLimiter{
+    fn default() -> Self {
+        Self::new(100_000)
+    }
+}
+
+impl LocalRateLimiter{
+    pub fn new(max_keys:usize)->Self{
+        Self{buckets:Mutex::default(),max_keys}
+    }
+    fn acquire_at(&self,key:&str,quota:Quota,now:Instant)->Option<Duration>{
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(b) = buckets.map.get_mut(key){
+            return b.acquire(quota,now)
+        }
+        let mut bucket = TokenBucket::new(quota,now);
+        let res = bucket.acquire(quota,now);
+        buckets.insert(key,bucket,self.max_keys,now);
+        res
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimiter for LocalRateLimiter{
+    async fn acquire(&self, key: &str, quota: Quota) -> Option<Duration> {
+        self.acquire_at(key,quota,Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::time::{Duration, Instant};
+    use crate::infra::rate_limit::{LocalRateLimiter, Quota};
+
+    #[test]
+    fn test_token_bucket(){
+        let limiter = LocalRateLimiter::new(2);
+        let quota = Quota::new(2.0,3);
+        let now = Instant::now();
+
+        for _ in 0..3{
+            assert!(limiter.acquire_at("a",quota,now).is_none());
+        }
+        let wait = limiter.acquire_at("a",quota,now).unwrap();
+        assert_eq!(wait,Duration::from_millis(500));
+        //other key has its own bucket
+        assert!(limiter.acquire_at("b",quota,now).is_none());
+
+        let now = now + Duration::from_millis(500);
+        assert!(limiter.acquire_at("a",quota,now).is_none());
+        assert!(limiter.acquire_at("a",quota,now).is_some());
+
+        //bucket b is full again and will be dropped
+        let now = now + Duration::from_secs(10);
+        assert!(limiter.acquire_at("c",quota,now).is_none());
+        assert_eq!(limiter.buckets.lock().unwrap().map.len(),1);
+
+        //swept in the interval, the oldest key is evicted for the new key
+        assert!(limiter.acquire_at("d",quota,now).is_none());
+        assert!(limiter.acquire_at("d",quota,now).is_none());
+        assert!(limiter.acquire_at("e",quota,now).is_none());
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.map.len(),2);
+        assert!(buckets.map.contains_key("d") && buckets.map.contains_key("e"));
+        assert_eq!(buckets.order,["d","e"]);
+    }
+}
@@ -0,0 +1,128 @@
+#[derive(Debug,PartialEq)]
+pub enum ClientHello{
+    //more data is needed
+    Incomplete,
+    //the server name of the client hello, none means the extension is absent
+    Complete(Option<String>),
+    Invalid,
+}
+
+struct Reader<'a>{
+    buf:&'a [u8],
+}
+
+impl<'a> Reader<'a>{
+    fn take(&mut self,n:usize)->Option<&'a [u8]>{
+        if self.buf.len() < n {
+            return None
+        }
+        let (a,b) = self.buf.split_at(n);
+        self.buf = b;
+        Some(a)
+    }
+    fn u8(&mut self)->Option<usize>{
+        self.take(1).map(|x|x[0] as usize)
+    }
+    fn u16(&mut self)->Option<usize>{
+        self.take(2).map(|x|((x[0] as usize) << 8) | x[1] as usize)
+    }
+    fn u24(&mut self)->Option<usize>{
+        self.take(3).map(|x|((x[0] as usize) << 16) | ((x[1] as usize) << 8) | x[2] as usize)
+    }
+    fn vec8(&mut self)->Option<&'a [u8]>{
+        let n = self.u8()?;
+        self.take(n)
+    }
+    fn vec16(&mut self)->Option<&'a [u8]>{
+        let n = self.u16()?;
+        self.take(n)
+    }
+}
+
+//read the sni of the tls client hello in the first record, rfc8446 4.1.2 and rfc6066 3
+pub fn parse_client_hello(buf:&[u8])->ClientHello{
+    if buf.len() < 5 {
+        return ClientHello::Incomplete
+    }
+    //handshake record
+    if buf[0] != 22 || buf[1] != 3 {
+        return ClientHello::Invalid
+    }
+    let len = ((buf[3] as usize) << 8) | buf[4] as usize;
+    if buf.len() < 5 + len {
+        return ClientHello::Incomplete
+    }
+    match server_name(&buf[5..5+len]) {
+        Some(s) => ClientHello::Complete(s),
+        None => ClientHello::Invalid,
+    }
+}
+
+fn server_name(record:&[u8])->Option<Option<String>>{
+    let mut r = Reader{buf:record};
+    //client hello
+    if r.u8()? != 1 {
+        return None
+    }
+    let len = r.u24()?;
+    let mut r = Reader{buf:r.take(len)?};
+    r.take(2 + 32)?; //version and random
+    r.vec8()?; //session id
+    r.vec16()?; //cipher suites
+    r.vec8()?; //compression methods
+    if r.buf.is_empty() {
+        return Some(None)
+    }
+    let mut ext = Reader{buf:r.vec16()?};
+    while !ext.buf.is_empty() {
+        let ty = ext.u16()?;
+        let data = ext.vec16()?;
+        if ty != 0 {
+            continue
+        }
+        let mut names = Reader{buf:Reader{buf:data}.vec16()?};
+        while !names.buf.is_empty() {
+            let name_type = names.u8()?;
+            let name = names.vec16()?;
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().map(|x|Some(x.to_ascii_lowercase()))
+            }
+        }
+    }
+    Some(None)
+}
+
+#[cfg(test)]
+mod test{
+    use crate::infra::sni::{ClientHello, parse_client_hello};
+
+    fn client_hello(host:&str)->Vec<u8>{
+        let mut sni = vec![0];
+        sni.extend((host.len() as u16).to_be_bytes());
+        sni.extend(host.as_bytes());
+        let mut ext = vec![0,0];
+        ext.extend((sni.len() as u16 + 2).to_be_bytes());
+        ext.extend((sni.len() as u16).to_be_bytes());
+        ext.extend(sni);
+        let mut body = vec![3,3];
+        body.extend([0u8;32]);
+        body.extend([0, 0,2,0x13,0x01, 1,0]);
+        body.extend((ext.len() as u16).to_be_bytes());
+        body.extend(ext);
+        let mut hs = vec![1,0];
+        hs.extend((body.len() as u16).to_be_bytes());
+        hs.extend(body);
+        let mut record = vec![22,3,1];
+        record.extend((hs.len() as u16).to_be_bytes());
+        record.extend(hs);
+        record
+    }
+
+    #[test]
+    fn test_parse_client_hello(){
+        let buf = client_hello("Test.com");
+        assert_eq!(parse_client_hello(&buf),ClientHello::Complete(Some("test.com".into())));
+        assert_eq!(parse_client_hello(&buf[..buf.len()-1]),ClientHello::Incomplete);
+        assert_eq!(parse_client_hello(b"GET / HTTP/1.1\r\n"),ClientHello::Invalid);
+    }
+}
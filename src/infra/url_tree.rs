@@ -16,17 +16,32 @@ impl<T> Default for Node<T> {
 
 impl<T> Node<T>{
     pub fn insert_path(&mut self,path:&str,data:Arc<T>){
-        if path == ""{
+        if path.is_empty(){
             self.data = Some(data);
             return;
         }
-        let ps = path.split('/').map(|x|if x == ""{ "*" }else { x}).rev().collect::<Vec<&str>>();
+        let ps = path.split('/').map(|x|if x.is_empty(){ "*" }else { x}).rev().collect::<Vec<&str>>();
         self.insert(ps,data);
     }
     pub fn find_by_path(&self,path:&str)->Option<Arc<T>>{
         let ps = path.split('/').rev().collect::<Vec<&str>>();
         self.find(ps)
     }
+    //the data inserted with the same path, not the longest prefix
+    pub fn get_path(&self,path:&str)->Option<Arc<T>>{
+        if path.is_empty(){
+            return self.data.clone()
+        }
+        let mut ps = path.split('/').map(|x|if x.is_empty(){ "*" }else { x});
+        if ps.next()? != self.path.as_str() {
+            return None
+        }
+        let mut node = self;
+        for p in ps{
+            node = node.next.get(p)?;
+        }
+        node.data.clone()
+    }
     pub fn insert(&mut self, mut ps:Vec<&str>, data:Arc<T>){
         let path = if let Some(s) = ps.pop(){
             s
@@ -59,11 +74,7 @@ impl<T> Node<T>{
         }
     }
     pub fn find(&self,mut ps:Vec<&str>)->Option<Arc<T>>{
-        let path = if let Some(s) = ps.pop(){
-            s
-        }else{
-            return None
-        };
+        let path = ps.pop()?;
 
         if self.path == "*" {
 
@@ -84,7 +95,7 @@ impl<T> Node<T>{
                 }
             }
         }
-        return self.data.clone()
+        self.data.clone()
     }
 }
 
@@ -133,5 +144,10 @@ mod test{
         root.insert_path("/api/v2/",Arc::new("api2"));
         let res = root.find_by_path("/api/v2/xxx").unwrap();
         assert_eq!(*res,"api2");
+
+        assert_eq!(*root.get_path("/api").unwrap(),"api");
+        assert_eq!(*root.get_path("/api/v2/").unwrap(),"api2");
+        assert!(root.get_path("/api/v1").is_none());
+        assert!(root.get_path("/api/v1/task/create/1").is_none());
     }
 }
@@ -1,3 +1,7 @@
+//the quic listener waits for the QUIC support of pingora
+#[cfg(feature = "http3")]
+compile_error!("the http3 feature is not available: pingora 0.1 has no QUIC listener");
+
 mod pkg;
 mod infra;
 mod service;
@@ -0,0 +1,769 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::infra::ip::CidrSet;
+
+pub const ANNOTATION_PREFIX:&str = "pingora.ingress/";
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct AnnotationError{
+    pub key:String,
+    pub value:String,
+    pub msg:String,
+}
+
+impl Display for AnnotationError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,"annotation[{}={}] {}",self.key,self.value,self.msg)
+    }
+}
+
+//read the annotations with prefix, record which key is used and which one is invalid
+pub struct AnnotationParser<'a>{
+    an:&'a BTreeMap<String,String>,
+    namespace:&'a str,
+    used:HashSet<String>,
+    errors:Vec<AnnotationError>,
+}
+
+impl<'a> AnnotationParser<'a>{
+    pub fn new(an:&'a BTreeMap<String,String>,namespace:&'a str)->Self{
+        Self{an,namespace,used:HashSet::new(),errors:vec![]}
+    }
+    pub fn get(&mut self,key:&str)->Option<&'a str>{
+        let key = format!("{}{}",ANNOTATION_PREFIX,key);
+        let an = self.an;
+        let value = an.get(key.as_str())?;
+        self.used.insert(key);
+        Some(value.trim())
+    }
+    //the annotation is set, valid or not
+    pub fn contains(&self,key:&str)->bool{
+        self.an.contains_key(format!("{}{}",ANNOTATION_PREFIX,key).as_str())
+    }
+    pub fn parse<T:FromStr>(&mut self,key:&str)->Option<T> where T::Err:Display{
+        let value = self.get(key)?;
+        match value.parse::<T>() {
+            Ok(o) => Some(o),
+            Err(e) => {
+                self.error(key,e);
+                None
+            }
+        }
+    }
+    //comma separated list, empty item is ignored
+    pub fn list(&mut self,key:&str)->Vec<String>{
+        if let Some(value) = self.get(key){
+            value.split(',').map(|x|x.trim()).filter(|x|!x.is_empty()).map(|x|x.to_string()).collect()
+        }else{
+            vec![]
+        }
+    }
+    //the name of an object, return namespace/name, default in the namespace of ingress
+    //the object should be in the namespace of the ingress, namespace/name of another namespace is rejected
+    pub fn object_name(&mut self,key:&str)->Option<String>{
+        let value = self.get(key)?;
+        match value.split_once('/') {
+            None => Some(format!("{}/{}",self.namespace,value)),
+            Some((ns,_)) if ns == self.namespace => Some(value.to_string()),
+            Some(_) => {
+                let msg = format!("{} is not in the namespace[{}] of the ingress",value,self.namespace);
+                self.error(key,msg);
+                None
+            }
+        }
+    }
+    //none when any item is invalid, a partial list is not used
+    pub fn cidr(&mut self,key:&str)->Option<CidrSet>{
+        let (set,invalid) = CidrSet::parse(&self.list(key));
+        if !invalid.is_empty() {
+            self.error(key,format!("invalid cidr:{:?}, all clients are denied",invalid));
+            return None
+        }
+        Some(set)
+    }
+    pub fn error<E:Display>(&mut self,key:&str,msg:E){
+        let key = format!("{}{}",ANNOTATION_PREFIX,key);
+        let value = self.an.get(key.as_str()).cloned().unwrap_or_default();
+        let err = AnnotationError{key,value,msg:msg.to_string()};
+        wd_log::log_warn_ln!("parse ingress {}",err);
+        self.errors.push(err);
+    }
+    pub fn finish(mut self)->Vec<AnnotationError>{
+        for (k,v) in self.an.iter(){
+            if k.starts_with(ANNOTATION_PREFIX) && !self.used.contains(k){
+                self.errors.push(AnnotationError{key:k.clone(),value:v.clone(),msg:"unknown annotation".into()})
+            }
+        }
+        self.errors
+    }
+}
+
+pub trait FromAnnotation:Sized{
+    fn from_annotation(p:&mut AnnotationParser)->Self;
+}
+
+//all behaviors of an ingress configured by annotations
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct IngPolicy{
+    pub timeout:IngTimeout,
+    pub rate_limit:Option<IngRateLimit>,
+    pub access:IngAccess,
+    pub basic_auth:Option<IngBasicAuth>,
+    pub ext_auth:Option<IngExtAuth>,
+    pub jwt:Option<IngJwt>,
+    pub cors:Option<IngCors>,
+    pub headers:IngHeaders,
+    pub rewrite:Option<IngRewrite>,
+    pub redirect:IngRedirect,
+    pub websocket:IngWebsocket,
+    pub backend:IngBackend,
+    pub canary:Option<IngCanary>,
+    pub mirror:Option<IngMirror>,
+}
+
+impl IngPolicy{
+    pub fn from_annotations(an:&BTreeMap<String,String>,namespace:&str)->(Self,Vec<AnnotationError>){
+        let mut p = AnnotationParser::new(an,namespace);
+        let policy = Self::from_annotation(&mut p);
+        (policy,p.finish())
+    }
+}
+
+impl FromAnnotation for IngPolicy{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let timeout = IngTimeout::from_annotation(p);
+        let rate_limit = Option::<IngRateLimit>::from_annotation(p);
+        let access = IngAccess::from_annotation(p);
+        let basic_auth = Option::<IngBasicAuth>::from_annotation(p);
+        let ext_auth = Option::<IngExtAuth>::from_annotation(p);
+        let jwt = Option::<IngJwt>::from_annotation(p);
+        let cors = Option::<IngCors>::from_annotation(p);
+        let headers = IngHeaders::from_annotation(p);
+        let rewrite = Option::<IngRewrite>::from_annotation(p);
+        let redirect = IngRedirect::from_annotation(p);
+        let websocket = IngWebsocket::from_annotation(p);
+        let backend = IngBackend::from_annotation(p);
+        let canary = Option::<IngCanary>::from_annotation(p);
+        let mirror = Option::<IngMirror>::from_annotation(p);
+        Self{timeout,rate_limit,access,basic_auth,ext_auth,jwt,cors,headers,rewrite,redirect,websocket,backend,canary,mirror}
+    }
+}
+
+//upstream timeout of an ingress, unit: second
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct IngTimeout{
+    pub connect:Option<u64>,
+    pub read:Option<u64>,
+    pub send:Option<u64>,
+    pub idle:Option<u64>,
+}
+
+impl FromAnnotation for IngTimeout{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        Self{
+            connect: p.parse("proxy-connect-timeout"),
+            read: p.parse("proxy-read-timeout"),
+            send: p.parse("proxy-send-timeout"),
+            idle: p.parse("proxy-idle-timeout"),
+        }
+    }
+}
+
+//request count per second of every key, burst default equal to rps
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct IngRateLimit{
+    pub rps:f64,
+    pub burst:u64,
+    pub key:IngLimitKey,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum IngLimitKey{
+    Ip,
+    Path,
+    Header(String),
+}
+
+impl FromStr for IngLimitKey{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ip" => return Ok(IngLimitKey::Ip),
+            "path" => return Ok(IngLimitKey::Path),
+            _ => {}
+        }
+        if let Some((ty,name)) = s.split_once(':') {
+            if ty.trim().eq_ignore_ascii_case("header") && !name.trim().is_empty() {
+                return Ok(IngLimitKey::Header(name.trim().to_lowercase()))
+            }
+        }
+        Err("expect ip, path or header:<name>".into())
+    }
+}
+
+impl FromAnnotation for Option<IngRateLimit>{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let key = p.parse::<IngLimitKey>("limit-key").unwrap_or(IngLimitKey::Ip);
+        let burst = p.parse::<u64>("limit-burst");
+        let rps = p.parse::<f64>("limit-rps")?;
+        if !rps.is_finite() || rps <= 0.0 {
+            p.error("limit-rps","must be greater than 0");
+            return None
+        }
+        let burst = burst.unwrap_or(rps.ceil() as u64).max(1);
+        Some(IngRateLimit{rps,burst,key})
+    }
+}
+
+//client ip access control, deny first
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct IngAccess{
+    pub allow:CidrSet,
+    pub deny:CidrSet,
+    //a range is invalid, all clients are denied instead of leaving the route open
+    pub invalid:bool,
+}
+
+impl FromAnnotation for IngAccess{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let allow = p.cidr("allowlist-source-range");
+        let deny = p.cidr("denylist-source-range");
+        Self{
+            invalid: allow.is_none() || deny.is_none(),
+            allow: allow.unwrap_or_default(),
+            deny: deny.unwrap_or_default(),
+        }
+    }
+}
+
+//http basic auth, the users are in the key `auth` of secret with htpasswd format
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct IngBasicAuth{
+    //empty when the annotations are invalid, all requests are denied
+    pub secret:String,
+    pub realm:String,
+}
+
+impl FromAnnotation for Option<IngBasicAuth>{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        if !p.contains("auth-type") && !p.contains("auth-secret") {
+            return None
+        }
+        let secret = p.object_name("auth-secret");
+        let realm = p.get("auth-realm").unwrap_or("Authentication Required").to_string();
+        //the invalid auth denies all requests instead of leaving the route open
+        let deny = IngBasicAuth{secret:String::new(),realm:realm.clone()};
+        match p.get("auth-type") {
+            Some("basic") => {}
+            Some(_) => {
+                p.error("auth-type","only basic is supported, all requests are denied");
+                return Some(deny)
+            }
+            None => {
+                p.error("auth-secret","auth-type is required, all requests are denied");
+                return Some(deny)
+            }
+        }
+        match secret {
+            Some(secret) => Some(IngBasicAuth{secret,realm}),
+            None => {
+                if !p.contains("auth-secret") {
+                    p.error("auth-type","auth-secret is required, all requests are denied");
+                }
+                Some(deny)
+            }
+        }
+    }
+}
+
+//external auth, every request is checked by a subrequest to the url before proxy.
+//2xx is allowed, 401 and 403 are returned to the client, others are error.
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct IngExtAuth{
+    //none when auth-url is invalid, all requests are denied
+    pub url:Option<url::Url>,
+    //the request headers sent to auth service
+    pub request_headers:Vec<String>,
+    //the headers of auth response copied to the upstream request
+    pub response_headers:Vec<String>,
+}
+
+impl FromAnnotation for Option<IngExtAuth>{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let mut request_headers = p.list("auth-request-headers");
+        if request_headers.is_empty() {
+            request_headers = vec!["Authorization".into(),"Cookie".into()];
+        }
+        let response_headers = p.list("auth-response-headers");
+        if !p.contains("auth-url") {
+            return None
+        }
+        //the invalid url denies all requests instead of leaving the route open
+        let url = match p.parse::<url::Url>("auth-url") {
+            Some(o) if o.scheme() == "http" || o.scheme() == "https" => Some(o),
+            Some(_) => {
+                p.error("auth-url","expect http or https url, all requests are denied");
+                None
+            }
+            None => None,
+        };
+        Some(IngExtAuth{url,request_headers,response_headers})
+    }
+}
+
+//jwt bearer token validation, the jwks is read from one of url, secret or file
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct IngJwt{
+    pub jwks:JwksSource,
+    pub issuer:Vec<String>,
+    pub audience:Vec<String>,
+    //claim name and the upstream request header name
+    pub claim_headers:Vec<(String,String)>,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum JwksSource{
+    Url(url::Url),
+    //namespace/name, the key `jwks.json` of the secret
+    Secret(String),
+    File(String),
+    //the jwks annotations are invalid, all requests are denied
+    Invalid,
+}
+
+impl FromAnnotation for Option<IngJwt>{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let issuer = p.list("jwt-issuer");
+        let audience = p.list("jwt-audience");
+        let mut claim_headers = vec![];
+        for i in p.list("jwt-claim-headers"){
+            match i.split_once(':') {
+                Some((claim,header)) if !claim.trim().is_empty() && !header.trim().is_empty() => {
+                    claim_headers.push((claim.trim().to_string(),header.trim().to_string()));
+                }
+                _ => p.error("jwt-claim-headers",format!("expect <claim>:<header>, found {}",i)),
+            }
+        }
+        let mut sources = vec![];
+        if let Some(url) = p.parse::<url::Url>("jwt-jwks-url"){
+            if url.scheme() == "http" || url.scheme() == "https" {
+                sources.push(JwksSource::Url(url));
+            }else{
+                p.error("jwt-jwks-url","expect http or https url");
+            }
+        }
+        if let Some(s) = p.object_name("jwt-jwks-secret"){
+            sources.push(JwksSource::Secret(s));
+        }
+        if let Some(s) = p.get("jwt-jwks-file"){
+            sources.push(JwksSource::File(s.to_string()));
+        }
+        if sources.len() > 1 {
+            p.error("jwt-jwks-url","only one of jwt-jwks-url, jwt-jwks-secret and jwt-jwks-file can be set, all requests are denied");
+            sources.clear();
+        }
+        //the invalid jwt denies all requests instead of leaving the route open
+        let jwks = match sources.pop() {
+            Some(o) => o,
+            None if ["jwt-jwks-url","jwt-jwks-secret","jwt-jwks-file"].iter().any(|x|p.contains(x)) => JwksSource::Invalid,
+            None => return None,
+        };
+        Some(IngJwt{jwks,issuer,audience,claim_headers})
+    }
+}
+
+//cross-origin resource sharing, the origin supports wildcard like `https://*.example.com`
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct IngCors{
+    pub allow_origin:Vec<String>,
+    pub allow_methods:String,
+    pub allow_headers:String,
+    pub expose_headers:String,
+    pub allow_credentials:bool,
+    pub max_age:u64,
+}
+
+impl FromAnnotation for Option<IngCors>{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let mut allow_origin = p.list("cors-allow-origin");
+        if allow_origin.is_empty() {
+            allow_origin.push("*".into());
+        }
+        let allow_methods = p.get("cors-allow-methods").unwrap_or("GET, PUT, POST, DELETE, PATCH, OPTIONS").to_string();
+        let allow_headers = p.get("cors-allow-headers")
+            .unwrap_or("DNT, Keep-Alive, User-Agent, X-Requested-With, If-Modified-Since, Cache-Control, Content-Type, Range, Authorization").to_string();
+        let expose_headers = p.get("cors-expose-headers").unwrap_or_default().to_string();
+        let allow_credentials = p.parse::<bool>("cors-allow-credentials").unwrap_or(false);
+        let max_age = p.parse::<u64>("cors-max-age").unwrap_or(86400);
+        if !p.parse::<bool>("enable-cors").unwrap_or(false) {
+            return None
+        }
+        Some(IngCors{allow_origin,allow_methods,allow_headers,expose_headers,allow_credentials,max_age})
+    }
+}
+
+//the variables can be used in header value
+pub const HEADER_VARIABLES:[&str;4] = ["${client_ip}","${host}","${request_id}","${path}"];
+
+//modify the headers of upstream request and downstream response
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct IngHeaders{
+    pub request:IngHeaderRules,
+    pub response:IngHeaderRules,
+}
+
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct IngHeaderRules{
+    pub remove:Vec<String>,
+    pub set:Vec<(String,String)>,
+    pub add:Vec<(String,String)>,
+}
+
+impl IngHeaderRules{
+    pub fn is_empty(&self)->bool{
+        self.remove.is_empty() && self.set.is_empty() && self.add.is_empty()
+    }
+    fn from_annotation(p: &mut AnnotationParser,ty:&str)->Self{
+        let remove_key = format!("{}-header-remove",ty);
+        let mut remove = vec![];
+        for name in p.list(remove_key.as_str()){
+            if http::HeaderName::from_bytes(name.as_bytes()).is_ok() {
+                remove.push(name);
+            }else{
+                p.error(remove_key.as_str(),format!("invalid header name {}",name));
+            }
+        }
+        Self{
+            remove,
+            set: IngHeaderRules::header_lines(p,format!("{}-header-set",ty).as_str()),
+            add: IngHeaderRules::header_lines(p,format!("{}-header-add",ty).as_str()),
+        }
+    }
+    //one header per line, `Name: value`
+    fn header_lines(p: &mut AnnotationParser,key:&str)->Vec<(String,String)>{
+        let value = if let Some(s) = p.get(key){ s }else{ return vec![] };
+        let mut list = vec![];
+        for line in value.lines().map(|x|x.trim()).filter(|x|!x.is_empty()){
+            let (name,value) = if let Some(s) = line.split_once(':'){ s }else{
+                p.error(key,format!("expect `Name: value`, found {}",line));
+                continue
+            };
+            let (name,value) = (name.trim(),value.trim());
+            let mut rest = value.to_string();
+            for v in HEADER_VARIABLES.iter(){
+                rest = rest.replace(v,"");
+            }
+            if http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                p.error(key,format!("invalid header name {}",name));
+            }else if rest.contains("${") {
+                p.error(key,format!("unknown variable in {}, expect one of {:?}",value,HEADER_VARIABLES));
+            }else if http::HeaderValue::from_str(rest.as_str()).is_err() {
+                p.error(key,format!("invalid header value {}",value));
+            }else{
+                list.push((name.to_string(),value.to_string()));
+            }
+        }
+        list
+    }
+}
+
+impl FromAnnotation for IngHeaders{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        Self{
+            request: IngHeaderRules::from_annotation(p,"request"),
+            response: IngHeaderRules::from_annotation(p,"response"),
+        }
+    }
+}
+
+//rewrite the path of upstream request.
+//the target replaces the matched prefix, or the whole path of regex with `$1` capture groups.
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum IngRewrite{
+    Target(String),
+    StripPrefix,
+}
+
+impl FromAnnotation for Option<IngRewrite>{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let strip = p.parse::<bool>("strip-prefix").unwrap_or(false);
+        if let Some(target) = p.get("rewrite-target"){
+            if strip {
+                p.error("strip-prefix","can not be used with rewrite-target");
+            }
+            if !target.starts_with('/') {
+                p.error("rewrite-target","must start with /");
+                return None
+            }
+            return Some(IngRewrite::Target(target.to_string()))
+        }
+        if strip {Some(IngRewrite::StripPrefix)}else{None}
+    }
+}
+
+//the redirects answered by gateway
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct IngRedirect{
+    //redirect http to https with 308 when the host has tls
+    pub ssl_redirect:bool,
+    //redirect all requests to the url with 301
+    pub permanent:Option<url::Url>,
+    //redirect all requests to the url with 302
+    pub temporal:Option<url::Url>,
+    pub www:Option<IngWwwRedirect>,
+}
+
+//redirect to the host with or without `www.` with 301
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub enum IngWwwRedirect{
+    Add,
+    Strip,
+}
+
+impl FromStr for IngWwwRedirect{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "add" => Ok(IngWwwRedirect::Add),
+            "strip" => Ok(IngWwwRedirect::Strip),
+            _ => Err("expect add or strip".into()),
+        }
+    }
+}
+
+impl FromAnnotation for IngRedirect{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let mut redirect = Self{
+            ssl_redirect: p.parse::<bool>("ssl-redirect").unwrap_or(false),
+            permanent: p.parse::<url::Url>("permanent-redirect"),
+            temporal: p.parse::<url::Url>("temporal-redirect"),
+            www: p.parse::<IngWwwRedirect>("www-redirect"),
+        };
+        if redirect.permanent.is_some() && redirect.temporal.is_some() {
+            p.error("temporal-redirect","can not be used with permanent-redirect");
+            redirect.temporal = None;
+        }
+        redirect
+    }
+}
+
+//the timeouts of upgraded connection, unit: second
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct IngWebsocket{
+    pub idle_timeout:Option<u64>,
+    //the max lifetime of a websocket session, 0 means not limit
+    pub max_duration:Option<u64>,
+}
+
+impl FromAnnotation for IngWebsocket{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        Self{
+            idle_timeout: p.parse("websocket-idle-timeout"),
+            max_duration: p.parse("websocket-max-duration"),
+        }
+    }
+}
+
+//how to connect the backend, none means tls is used when the host has tls
+#[derive(Default,Debug,Clone,Serialize,Deserialize)]
+pub struct IngBackend{
+    pub protocol:Option<IngBackendProtocol>,
+    //verify the certificate of https backend
+    pub ssl_verify:bool,
+    //the tls stream of the host is forwarded to the backend without termination
+    pub ssl_passthrough:bool,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Serialize,Deserialize)]
+pub enum IngBackendProtocol{
+    Http,
+    //http2 over cleartext
+    H2c,
+    Https,
+    Grpc,
+    Grpcs,
+}
+
+impl IngBackendProtocol{
+    pub fn is_tls(&self)->bool{
+        matches!(self,IngBackendProtocol::Https | IngBackendProtocol::Grpcs)
+    }
+    //max and min http version
+    pub fn http_version(&self)->(u8,u8){
+        match self {
+            IngBackendProtocol::Http | IngBackendProtocol::Https => (1,1),
+            _ => (2,2),
+        }
+    }
+}
+
+impl FromStr for IngBackendProtocol{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "HTTP" | "HTTP1" => Ok(IngBackendProtocol::Http),
+            "HTTP2" | "H2C" => Ok(IngBackendProtocol::H2c),
+            "HTTPS" => Ok(IngBackendProtocol::Https),
+            "GRPC" => Ok(IngBackendProtocol::Grpc),
+            "GRPCS" => Ok(IngBackendProtocol::Grpcs),
+            _ => Err("expect HTTP, HTTP2, HTTPS, GRPC or GRPCS".into()),
+        }
+    }
+}
+
+impl FromAnnotation for IngBackend{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        Self{
+            protocol: p.parse("backend-protocol"),
+            ssl_verify: p.parse("proxy-ssl-verify").unwrap_or(false),
+            ssl_passthrough: p.parse("ssl-passthrough").unwrap_or(false),
+        }
+    }
+}
+
+//nginx-ingress style canary, the ingress overlays the primary ingress of the same host and path.
+//the header is checked first, then the cookie, then the weight
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct IngCanary{
+    pub by_header:Option<String>,
+    pub by_header_value:Option<String>,
+    pub by_header_pattern:Option<String>,
+    pub by_cookie:Option<String>,
+    pub weight:u32,
+    pub weight_total:u32,
+}
+
+impl FromAnnotation for Option<IngCanary>{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let enable = p.parse::<bool>("canary").unwrap_or(false);
+        let mut canary = IngCanary{
+            by_header: p.get("canary-by-header").map(|x|x.to_string()),
+            by_header_value: p.get("canary-by-header-value").map(|x|x.to_string()),
+            by_header_pattern: p.get("canary-by-header-pattern").map(|x|x.to_string()),
+            by_cookie: p.get("canary-by-cookie").map(|x|x.to_string()),
+            weight: p.parse("canary-weight").unwrap_or(0),
+            weight_total: p.parse("canary-weight-total").unwrap_or(100),
+        };
+        if !enable {
+            if canary != (IngCanary{weight_total:100,..Default::default()}) {
+                p.error("canary","the canary annotations need canary: true");
+            }
+            return None
+        }
+        if let Some(ref h) = canary.by_header {
+            if http::HeaderName::from_bytes(h.as_bytes()).is_err() {
+                p.error("canary-by-header",format!("invalid header name {}",h));
+                canary.by_header = None;
+            }
+        }
+        if let Some(ref r) = canary.by_header_pattern {
+            if let Err(e) = regex::Regex::new(r.as_str()) {
+                p.error("canary-by-header-pattern",format!("invalid regex:{}",e));
+                canary.by_header_pattern = None;
+            }
+        }
+        if (canary.by_header_value.is_some() || canary.by_header_pattern.is_some()) && canary.by_header.is_none() {
+            p.error("canary-by-header","is required by canary-by-header-value and canary-by-header-pattern");
+        }
+        if canary.weight_total == 0 || canary.weight > canary.weight_total {
+            p.error("canary-weight",format!("should be in 0..={}",canary.weight_total));
+            canary.weight = 0;
+            canary.weight_total = 100;
+        }
+        Some(canary)
+    }
+}
+
+//the body is buffered by pingora up to 64k for the retry, a larger body is not mirrored
+pub const MIRROR_BODY_MAX:usize = 64 * 1024;
+
+//a copy of the request is sent to the target in background, the response is discarded.
+//the path of the request is appended to the path of the target
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct IngMirror{
+    pub target:url::Url,
+    //0-100
+    pub percentage:u32,
+    pub body_max:usize,
+}
+
+impl FromAnnotation for Option<IngMirror>{
+    fn from_annotation(p: &mut AnnotationParser) -> Self {
+        let percentage = p.parse::<u32>("mirror-percentage").unwrap_or(100);
+        let body_max = p.parse::<usize>("mirror-request-body-max").unwrap_or(MIRROR_BODY_MAX);
+        let target = p.parse::<url::Url>("mirror-target")?;
+        if target.scheme() != "http" && target.scheme() != "https" {
+            p.error("mirror-target","expect http or https url");
+            return None
+        }
+        if percentage > 100 {
+            p.error("mirror-percentage","should be in 0..=100");
+            return None
+        }
+        if body_max > MIRROR_BODY_MAX {
+            p.error("mirror-request-body-max",format!("should be no more than {}",MIRROR_BODY_MAX));
+        }
+        Some(IngMirror{target,percentage,body_max:body_max.min(MIRROR_BODY_MAX)})
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use crate::pkg::annotation::IngPolicy;
+
+    #[test]
+    fn test_policy_from_annotations(){
+        let mut an = BTreeMap::new();
+        an.insert("pingora.ingress/proxy-connect-timeout".to_string(),"3".to_string());
+        an.insert("pingora.ingress/proxy-read-timeout".to_string(),"3s".to_string());
+        an.insert("pingora.ingress/unknown-key".to_string(),"1".to_string());
+        an.insert("kubectl.kubernetes.io/last-applied-configuration".to_string(),"{}".to_string());
+
+        let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+        assert_eq!(policy.timeout.connect,Some(3));
+        assert_eq!(policy.timeout.read,None);
+        assert_eq!(errors.len(),2);
+        assert_eq!(errors[0].key,"pingora.ingress/proxy-read-timeout");
+        assert_eq!(errors[1].key,"pingora.ingress/unknown-key");
+    }
+
+    #[test]
+    fn test_canary_from_annotations(){
+        let mut an = BTreeMap::new();
+        an.insert("pingora.ingress/canary-weight".to_string(),"20".to_string());
+        let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+        assert!(policy.canary.is_none());
+        assert_eq!(errors[0].key,"pingora.ingress/canary");
+
+        an.insert("pingora.ingress/canary".to_string(),"true".to_string());
+        an.insert("pingora.ingress/canary-by-header".to_string(),"x-canary".to_string());
+        an.insert("pingora.ingress/canary-by-header-pattern".to_string(),"(".to_string());
+        let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+        let canary = policy.canary.unwrap();
+        assert_eq!((canary.weight,canary.weight_total),(20,100));
+        assert_eq!(canary.by_header.as_deref(),Some("x-canary"));
+        assert!(canary.by_header_pattern.is_none());
+        assert_eq!(errors.len(),1);
+    }
+
+    #[test]
+    fn test_secret_in_ingress_namespace(){
+        let mut an = BTreeMap::new();
+        an.insert("pingora.ingress/auth-type".to_string(),"basic".to_string());
+        an.insert("pingora.ingress/auth-secret".to_string(),"qa/users".to_string());
+        let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+        assert_eq!(policy.basic_auth.unwrap().secret,"qa/users");
+        assert!(errors.is_empty());
+
+        an.insert("pingora.ingress/auth-secret".to_string(),"kube-system/users".to_string());
+        let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+        assert_eq!(policy.basic_auth.unwrap().secret,"");
+        assert_eq!(errors[0].key,"pingora.ingress/auth-secret");
+    }
+}
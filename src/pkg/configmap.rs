@@ -0,0 +1,36 @@
+use std::collections::BTreeMap;
+use async_channel::Receiver;
+use futures::prelude::*;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{Api, Client};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::runtime::watcher::Event;
+
+//watch one configmap by namespace/name, the whole data is sent when it changed, and empty when deleted
+pub async fn watch_config_map(key:&str)->anyhow::Result<Receiver<BTreeMap<String,String>>>{
+    let (namespace,name) = key.split_once('/').ok_or_else(||anyhow::anyhow!("configmap[{}] should be namespace/name",key))?;
+    let (sender,receiver) = async_channel::bounded(2);
+    let client = Client::try_default().await?;
+    let api:Api<ConfigMap> = Api::namespaced(client,namespace);
+    let wc = watcher::Config::default().fields(format!("metadata.name={}",name).as_str());
+    let mut watch = watcher(api, wc).default_backoff().boxed();
+    let key = key.to_string();
+    tokio::spawn(async move {
+        while let Some(result) = watch.next().await{
+            let data = match result{
+                Ok(Event::Applied(cm)) => cm.data.unwrap_or_default(),
+                Ok(Event::Deleted(_)) => BTreeMap::new(),
+                Ok(Event::Restarted(list)) => list.into_iter().next().and_then(|x|x.data).unwrap_or_default(),
+                Err(e) => {
+                    wd_log::log_error_ln!("watch configmap[{}] event error:{:?}",key,e);
+                    continue
+                }
+            };
+            if let Err(e) = sender.send(data).await{
+                wd_log::log_error_ln!("configmap[{}] send error:{}",key,e);
+                return
+            }
+        }
+    });
+    Ok(receiver)
+}
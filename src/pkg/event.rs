@@ -0,0 +1,92 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use k8s_openapi::api::core::v1::ObjectReference;
+use kube::Client;
+use kube::runtime::events::{Event, EventType, Recorder, Reporter};
+use crate::pkg::leader;
+use crate::pkg::pod::PodApi;
+
+const EVENT_REPORTER_CONTROLLER:&str = "pingora-ingress";
+const EVENT_SENT_CACHE_MAX:usize = 4096;
+
+//a kubernetes event waiting to be published against an object
+#[derive(Debug,Clone)]
+pub struct IngReport{
+    pub reference:ObjectReference,
+    pub ty:EventType,
+    pub reason:String,
+    pub action:String,
+    pub note:String,
+}
+
+impl IngReport{
+    pub fn warning<R:Into<String>,N:Into<String>>(reference:ObjectReference,reason:R,note:N)->Self{
+        Self{reference,ty:EventType::Warning,reason:reason.into(),action:"Sync".into(),note:note.into()}
+    }
+    pub fn normal<R:Into<String>,N:Into<String>>(reference:ObjectReference,reason:R,note:N)->Self{
+        Self{reference,ty:EventType::Normal,reason:reason.into(),action:"Sync".into(),note:note.into()}
+    }
+    fn key(&self)->String{
+        format!("{}/{}/{}/{}",
+                self.reference.uid.as_deref().unwrap_or_default(),
+                self.reference.resource_version.as_deref().unwrap_or_default(),
+                self.reason,self.note)
+    }
+}
+
+#[derive(Clone)]
+pub struct EventRecorder{
+    client:Client,
+    reporter:Reporter,
+    //the same object version only report once, watcher restart will resend all ingress
+    sent:Arc<Mutex<HashSet<String>>>,
+}
+
+impl EventRecorder{
+    pub fn new(client:Client)->Self{
+        let pod_name = PodApi::pod_name();
+        let reporter = Reporter{
+            controller: EVENT_REPORTER_CONTROLLER.into(),
+            instance: if pod_name.is_empty() {None}else{Some(pod_name)},
+        };
+        Self{client,reporter,sent:Arc::default()}
+    }
+    fn first_send(&self,report:&IngReport)->bool{
+        let mut sent = self.sent.lock().unwrap();
+        if sent.len() >= EVENT_SENT_CACHE_MAX {
+            sent.clear();
+        }
+        sent.insert(report.key())
+    }
+    //only the leader publishes, the reports of the followers are dropped
+    pub async fn publish(&self,report:IngReport){
+        if !leader::is_leader() || !self.first_send(&report) {
+            return;
+        }
+        let IngReport{ reference, ty, reason, action, note } = report;
+        let name = reference.name.clone().unwrap_or_default();
+        let recorder = Recorder::new(self.client.clone(),self.reporter.clone(),reference);
+        let event = Event{
+            type_: ty,
+            reason,
+            note: Some(note),
+            action,
+            secondary: None,
+        };
+        if let Err(e) = recorder.publish(event).await{
+            wd_log::log_error_ln!("publish event to object[{}] error:{}",name,e);
+        }
+    }
+    //publish in background, do not block the watcher
+    pub fn publish_all(&self,reports:Vec<IngReport>){
+        if reports.is_empty() {
+            return;
+        }
+        let this = self.clone();
+        tokio::spawn(async move{
+            for i in reports{
+                this.publish(i).await;
+            }
+        });
+    }
+}
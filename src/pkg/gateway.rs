@@ -0,0 +1,1026 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use async_channel::Sender;
+use futures::prelude::*;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, ObjectMeta, Time};
+use k8s_openapi::chrono::Utc;
+use k8s_openapi::NamespaceResourceScope;
+use kube::{Api, Client, CustomResource, Resource, ResourceExt};
+use kube::api::{Patch, PatchParams};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::runtime::watcher::Event;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use crate::pkg::annotation::{IngBackendProtocol, IngHeaderRules, IngPolicy, IngRewrite};
+use crate::pkg::leader;
+use crate::pkg::ingress::{IngBackendRef, IngConditions, IngHost, IngressEvent, IngRule, IngSni, IngValueMatch};
+
+pub const GATEWAY_CONTROLLER_NAME:&str = "pingora.ingress/gateway-controller";
+const GATEWAY_GROUP:&str = "gateway.networking.k8s.io";
+
+//the gateway api v1 resources, only the fields used by the gateway are defined
+#[derive(CustomResource,Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[kube(group="gateway.networking.k8s.io",version="v1",kind="GatewayClass",schema="disabled")]
+#[serde(rename_all="camelCase")]
+pub struct GatewayClassSpec{
+    pub controller_name:String,
+}
+
+#[derive(CustomResource,Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[kube(group="gateway.networking.k8s.io",version="v1",kind="Gateway",namespaced,schema="disabled")]
+#[serde(rename_all="camelCase")]
+pub struct GatewaySpec{
+    pub gateway_class_name:String,
+    #[serde(default)]
+    pub listeners:Vec<Listener>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct Listener{
+    pub name:String,
+    pub hostname:Option<String>,
+    pub port:i32,
+    pub protocol:String,
+    pub tls:Option<GatewayTlsConfig>,
+    pub allowed_routes:Option<AllowedRoutes>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct GatewayTlsConfig{
+    pub mode:Option<String>,
+    #[serde(default)]
+    pub certificate_refs:Vec<ObjectReference>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct AllowedRoutes{
+    pub namespaces:Option<RouteNamespaces>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct RouteNamespaces{
+    pub from:Option<String>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct ObjectReference{
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub group:Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub kind:Option<String>,
+    pub name:String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub namespace:Option<String>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct ParentReference{
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub group:Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub kind:Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub namespace:Option<String>,
+    pub name:String,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub section_name:Option<String>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub port:Option<i32>,
+}
+
+#[derive(CustomResource,Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[kube(group="gateway.networking.k8s.io",version="v1",kind="HTTPRoute",namespaced,status="RouteStatus",schema="disabled")]
+#[serde(rename_all="camelCase")]
+pub struct HTTPRouteSpec{
+    #[serde(default)]
+    pub parent_refs:Vec<ParentReference>,
+    #[serde(default)]
+    pub hostnames:Vec<String>,
+    #[serde(default)]
+    pub rules:Vec<HTTPRouteRule>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct HTTPRouteRule{
+    #[serde(default)]
+    pub matches:Vec<HTTPRouteMatch>,
+    #[serde(default)]
+    pub filters:Vec<HTTPRouteFilter>,
+    #[serde(default)]
+    pub backend_refs:Vec<BackendRef>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct HTTPRouteMatch{
+    pub path:Option<ValueMatch>,
+    #[serde(default)]
+    pub headers:Vec<ValueMatch>,
+    #[serde(default)]
+    pub query_params:Vec<ValueMatch>,
+    pub method:Option<String>,
+}
+
+//the path match has no name, the header and query match have no default type
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct ValueMatch{
+    #[serde(rename="type")]
+    pub type_:Option<String>,
+    #[serde(default)]
+    pub name:String,
+    pub value:Option<String>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct HTTPRouteFilter{
+    #[serde(rename="type")]
+    pub type_:String,
+    pub request_header_modifier:Option<HeaderFilter>,
+    pub response_header_modifier:Option<HeaderFilter>,
+    pub request_redirect:Option<RequestRedirectFilter>,
+    pub url_rewrite:Option<UrlRewriteFilter>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct HeaderFilter{
+    #[serde(default)]
+    pub set:Vec<HttpHeader>,
+    #[serde(default)]
+    pub add:Vec<HttpHeader>,
+    #[serde(default)]
+    pub remove:Vec<String>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct HttpHeader{
+    pub name:String,
+    pub value:String,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct RequestRedirectFilter{
+    pub scheme:Option<String>,
+    pub hostname:Option<String>,
+    pub path:Option<PathModifier>,
+    pub port:Option<i32>,
+    pub status_code:Option<u16>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct UrlRewriteFilter{
+    pub hostname:Option<String>,
+    pub path:Option<PathModifier>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct PathModifier{
+    #[serde(rename="type")]
+    pub type_:String,
+    pub replace_full_path:Option<String>,
+    pub replace_prefix_match:Option<String>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct BackendRef{
+    pub group:Option<String>,
+    pub kind:Option<String>,
+    pub name:String,
+    pub namespace:Option<String>,
+    pub port:Option<i32>,
+    pub weight:Option<u32>,
+    #[serde(default)]
+    pub filters:Vec<serde_json::Value>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct RouteStatus{
+    #[serde(default)]
+    pub parents:Vec<RouteParentStatus>,
+}
+
+#[derive(Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct RouteParentStatus{
+    pub parent_ref:ParentReference,
+    pub controller_name:String,
+    #[serde(default)]
+    pub conditions:Vec<Condition>,
+}
+
+#[derive(CustomResource,Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[kube(group="gateway.networking.k8s.io",version="v1",kind="GRPCRoute",namespaced,status="RouteStatus",schema="disabled")]
+#[serde(rename_all="camelCase")]
+pub struct GRPCRouteSpec{
+    #[serde(default)]
+    pub parent_refs:Vec<ParentReference>,
+    #[serde(default)]
+    pub hostnames:Vec<String>,
+    #[serde(default)]
+    pub rules:Vec<GRPCRouteRule>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct GRPCRouteRule{
+    #[serde(default)]
+    pub matches:Vec<GRPCRouteMatch>,
+    #[serde(default)]
+    pub filters:Vec<HTTPRouteFilter>,
+    #[serde(default)]
+    pub backend_refs:Vec<BackendRef>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct GRPCRouteMatch{
+    pub method:Option<GRPCMethodMatch>,
+    #[serde(default)]
+    pub headers:Vec<ValueMatch>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct GRPCMethodMatch{
+    #[serde(rename="type")]
+    pub type_:Option<String>,
+    pub service:Option<String>,
+    pub method:Option<String>,
+}
+
+#[derive(CustomResource,Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[kube(group="gateway.networking.k8s.io",version="v1alpha2",kind="TLSRoute",namespaced,status="RouteStatus",schema="disabled")]
+#[serde(rename_all="camelCase")]
+pub struct TLSRouteSpec{
+    #[serde(default)]
+    pub parent_refs:Vec<ParentReference>,
+    #[serde(default)]
+    pub hostnames:Vec<String>,
+    #[serde(default)]
+    pub rules:Vec<TLSRouteRule>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct TLSRouteRule{
+    #[serde(default)]
+    pub backend_refs:Vec<BackendRef>,
+}
+
+//the reason of a route condition
+struct RouteIssue{
+    reason:&'static str,
+    msg:String,
+}
+
+impl RouteIssue{
+    fn new<S:Into<String>>(reason:&'static str,msg:S)->Self{
+        Self{reason,msg:msg.into()}
+    }
+}
+
+fn condition(ty:&str,ok:bool,reason:&str,msg:String,generation:Option<i64>)->Condition{
+    Condition{
+        last_transition_time: Time(Utc::now()),
+        message: msg,
+        observed_generation: generation,
+        reason: reason.into(),
+        status: if ok {"True"}else{"False"}.into(),
+        type_: ty.into(),
+    }
+}
+
+//the router key of a hostname, the wildcard host keeps its `*.` and does not match the parent domain itself
+fn router_host(host:&str)->String{
+    host.to_lowercase()
+}
+
+fn hostname_match(pattern:&str,host:&str)->bool{
+    match pattern.strip_prefix('*') {
+        Some(suffix) => host.ends_with(suffix) && host.len() > suffix.len(),
+        None => pattern == host,
+    }
+}
+
+//the hostnames of a route attached to a listener, rfc gateway api HTTPRoute.spec.hostnames
+fn listener_hostnames(listener:Option<&str>,route:&[String])->Vec<String>{
+    match (listener,route.is_empty()) {
+        (None,true) => vec!["*".into()],
+        (None,false) => route.to_vec(),
+        (Some(l),true) => vec![l.to_string()],
+        (Some(l),false) => route.iter().filter_map(|r|{
+            if hostname_match(l,r) {
+                Some(r.clone())
+            }else if hostname_match(r,l) {
+                Some(l.to_string())
+            }else{
+                None
+            }
+        }).collect(),
+    }
+}
+
+fn header_rules(f:&HeaderFilter)->IngHeaderRules{
+    IngHeaderRules{
+        remove: f.remove.clone(),
+        set: f.set.iter().map(|x|(x.name.clone(),x.value.clone())).collect(),
+        add: f.add.iter().map(|x|(x.name.clone(),x.value.clone())).collect(),
+    }
+}
+
+fn value_match(m:&ValueMatch)->Result<IngValueMatch,RouteIssue>{
+    let value = m.value.clone().unwrap_or_default();
+    let regex = match m.type_.as_deref().unwrap_or("Exact") {
+        "Exact" => false,
+        "RegularExpression" => {
+            if let Err(e) = regex::Regex::new(value.as_str()) {
+                return Err(RouteIssue::new("UnsupportedValue",format!("match[{}] invalid regex:{}",m.name,e)))
+            }
+            true
+        }
+        s => return Err(RouteIssue::new("UnsupportedValue",format!("match[{}] type {} is not supported",m.name,s))),
+    };
+    Ok(IngValueMatch{name:m.name.clone(),value,regex})
+}
+
+//a match of the rule is a rule of router
+fn route_match(m:&HTTPRouteMatch)->Result<(String,u8,IngConditions),RouteIssue>{
+    let (path,ty) = match m.path {
+        None => ("/".to_string(),1),
+        Some(ref p) => {
+            let path = p.value.clone().unwrap_or("/".into());
+            let ty = match p.type_.as_deref().unwrap_or("PathPrefix") {
+                "PathPrefix" => 1,
+                "Exact" => 2,
+                "RegularExpression" => {
+                    if let Err(e) = regex::Regex::new(format!("^(?:{})",path).as_str()) {
+                        return Err(RouteIssue::new("UnsupportedValue",format!("path[{}] invalid regex:{}",path,e)))
+                    }
+                    3
+                }
+                s => return Err(RouteIssue::new("UnsupportedValue",format!("path type {} is not supported",s))),
+            };
+            (path,ty)
+        }
+    };
+    let conditions = IngConditions{
+        headers: m.headers.iter().map(value_match).collect::<Result<Vec<_>,_>>()?,
+        query: m.query_params.iter().map(value_match).collect::<Result<Vec<_>,_>>()?,
+        method: m.method.clone(),
+    };
+    Ok((path,ty,conditions))
+}
+
+fn rule_policy(filters:&[HTTPRouteFilter],path_types:&[u8],issues:&mut Vec<RouteIssue>)->IngPolicy{
+    let mut policy = IngPolicy::default();
+    for f in filters.iter(){
+        match (f.type_.as_str(),&f.request_header_modifier,&f.response_header_modifier,&f.request_redirect,&f.url_rewrite) {
+            ("RequestHeaderModifier",Some(h),_,_,_) => {
+                let rules = header_rules(h);
+                policy.headers.request.remove.extend(rules.remove);
+                policy.headers.request.set.extend(rules.set);
+                policy.headers.request.add.extend(rules.add);
+            }
+            ("ResponseHeaderModifier",_,Some(h),_,_) => policy.headers.response = header_rules(h),
+            ("RequestRedirect",_,_,Some(r),_) => {
+                //the redirect is a fixed url, the host of request is not known here
+                let host = if let Some(ref h) = r.hostname{ h }else{
+                    issues.push(RouteIssue::new("UnsupportedValue","requestRedirect without hostname is not supported"));
+                    continue
+                };
+                let mut url = format!("{}://{}",r.scheme.as_deref().unwrap_or("http"),host);
+                if let Some(port) = r.port{
+                    url.push_str(format!(":{}",port).as_str());
+                }
+                url.push_str(r.path.as_ref().and_then(|x|x.replace_full_path.as_deref()).unwrap_or("/"));
+                match url.parse::<url::Url>() {
+                    Ok(u) if r.status_code == Some(302) => policy.redirect.temporal = Some(u),
+                    Ok(u) => policy.redirect.permanent = Some(u),
+                    Err(e) => issues.push(RouteIssue::new("UnsupportedValue",format!("requestRedirect url[{}]:{}",url,e))),
+                }
+            }
+            ("URLRewrite",_,_,_,Some(r)) => {
+                if let Some(ref h) = r.hostname{
+                    policy.headers.request.set.push(("Host".into(),h.clone()));
+                }
+                match r.path {
+                    Some(PathModifier{replace_prefix_match:Some(ref p),..}) => policy.rewrite = Some(IngRewrite::Target(p.clone())),
+                    //a full path replacement of prefix match would keep the rest of path
+                    Some(PathModifier{replace_full_path:Some(ref p),..}) if path_types.iter().all(|x|*x == 2) => policy.rewrite = Some(IngRewrite::Target(p.clone())),
+                    Some(_) => issues.push(RouteIssue::new("UnsupportedValue","urlRewrite ReplaceFullPath is only supported with Exact path match")),
+                    None => {}
+                }
+            }
+            (ty,..) => issues.push(RouteIssue::new("UnsupportedValue",format!("filter {} is not supported",ty))),
+        }
+    }
+    policy
+}
+
+fn rule_backends(refs:&[BackendRef],namespace:&str,issues:&mut Vec<RouteIssue>)->Vec<IngBackendRef>{
+    let mut backends = vec![];
+    for b in refs.iter(){
+        if b.group.as_deref().unwrap_or_default() != "" || b.kind.as_deref().unwrap_or("Service") != "Service" {
+            issues.push(RouteIssue::new("InvalidKind",format!("backendRef[{}] only Service is supported",b.name)));
+            continue
+        }
+        //ReferenceGrant is not supported, so the backend should be in the namespace of route
+        if b.namespace.as_deref().map(|x|x != namespace).unwrap_or(false) {
+            issues.push(RouteIssue::new("RefNotPermitted",format!("backendRef[{}] in another namespace is not permitted",b.name)));
+            continue
+        }
+        let port = if let Some(p) = b.port{ p }else{
+            issues.push(RouteIssue::new("UnsupportedValue",format!("backendRef[{}] port is required",b.name)));
+            continue
+        };
+        if !b.filters.is_empty() {
+            issues.push(RouteIssue::new("UnsupportedValue",format!("backendRef[{}] filters are not supported",b.name)));
+        }
+        backends.push(IngBackendRef{backend:format!("{}.{}",b.name,namespace),port,weight:b.weight.unwrap_or(1)});
+    }
+    backends
+}
+
+//the router rules of a rule, a rule is inserted once for each match
+fn match_rules(list:Vec<(String,u8,IngConditions)>,policy:IngPolicy,backends:Vec<IngBackendRef>)->Vec<IngRule>{
+    //no available backend is responded with 500
+    let (backend,port) = backends.first().map(|x|(x.backend.clone(),x.port)).unwrap_or_default();
+    list.into_iter().map(|(path,ty,conditions)|{
+        IngRule{path,ty,backend:backend.clone(),port,policy:policy.clone(),conditions,backends:backends.clone(),owner:String::new(),tls_only:false}
+    }).collect()
+}
+
+//the grpc request path is /service/method, rfc gateway api GRPCMethodMatch
+fn grpc_match(m:&GRPCRouteMatch)->Result<(String,u8,IngConditions),RouteIssue>{
+    let method = m.method.clone().unwrap_or_default();
+    let (path,ty) = match (method.type_.as_deref().unwrap_or("Exact"),method.service,method.method) {
+        ("Exact",Some(s),Some(m)) => (format!("/{}/{}",s,m),2),
+        ("Exact",Some(s),None) => (format!("/{}",s),1),
+        ("Exact",None,Some(m)) => (format!("/[^/]+/{}$",regex::escape(m.as_str())),3),
+        ("Exact",None,None) => ("/".to_string(),1),
+        ("RegularExpression",s,m) => {
+            let path = format!("/(?:{})/(?:{})$",s.as_deref().unwrap_or("[^/]+"),m.as_deref().unwrap_or("[^/]+"));
+            if let Err(e) = regex::Regex::new(path.as_str()) {
+                return Err(RouteIssue::new("UnsupportedValue",format!("grpc method invalid regex:{}",e)))
+            }
+            (path,3)
+        }
+        (s,..) => return Err(RouteIssue::new("UnsupportedValue",format!("grpc method match type {} is not supported",s))),
+    };
+    let conditions = IngConditions{
+        headers: m.headers.iter().map(value_match).collect::<Result<Vec<_>,_>>()?,
+        ..Default::default()
+    };
+    Ok((path,ty,conditions))
+}
+
+fn resource_key<K:Resource>(k:&K)->String{
+    match k.meta().namespace {
+        Some(ref ns) => format!("{}/{}",ns,k.meta().name.as_deref().unwrap_or_default()),
+        None => k.meta().name.clone().unwrap_or_default(),
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub enum RouteKind{
+    Http,
+    Grpc,
+    Tls,
+}
+
+//the common part of the route kinds
+trait GatewayRoute{
+    fn route_kind(&self)->RouteKind;
+    fn route_meta(&self)->&ObjectMeta;
+    fn parent_refs(&self)->&[ParentReference];
+    fn hostnames(&self)->&[String];
+    fn route_status(&self)->Option<&RouteStatus>;
+    //the router rules, the invalid match or filter is skipped and reported
+    fn rules(&self,issues:&mut Vec<RouteIssue>)->Vec<IngRule>;
+    //whether the route can attach to the listener
+    fn allow_listener(&self,l:&Listener)->bool{
+        l.protocol == "HTTP" || l.protocol == "HTTPS"
+    }
+}
+
+impl GatewayRoute for HTTPRoute{
+    fn route_kind(&self)->RouteKind{ RouteKind::Http }
+    fn route_meta(&self)->&ObjectMeta{ &self.metadata }
+    fn parent_refs(&self)->&[ParentReference]{ &self.spec.parent_refs }
+    fn hostnames(&self)->&[String]{ &self.spec.hostnames }
+    fn route_status(&self)->Option<&RouteStatus>{ self.status.as_ref() }
+    fn rules(&self,issues:&mut Vec<RouteIssue>)->Vec<IngRule>{
+        let namespace = self.namespace().unwrap_or_default();
+        let mut rules = vec![];
+        for r in self.spec.rules.iter(){
+            let default_match = [HTTPRouteMatch::default()];
+            let matches = if r.matches.is_empty() { &default_match[..] }else{ &r.matches[..] };
+            let mut list = vec![];
+            for m in matches.iter(){
+                match route_match(m) {
+                    Ok(o) => list.push(o),
+                    Err(e) => issues.push(e),
+                }
+            }
+            let path_types = list.iter().map(|x|x.1).collect::<Vec<_>>();
+            let policy = rule_policy(&r.filters,&path_types,issues);
+            let backends = rule_backends(&r.backend_refs,namespace.as_str(),issues);
+            rules.extend(match_rules(list,policy,backends));
+        }
+        rules
+    }
+}
+
+impl GatewayRoute for GRPCRoute{
+    fn route_kind(&self)->RouteKind{ RouteKind::Grpc }
+    fn route_meta(&self)->&ObjectMeta{ &self.metadata }
+    fn parent_refs(&self)->&[ParentReference]{ &self.spec.parent_refs }
+    fn hostnames(&self)->&[String]{ &self.spec.hostnames }
+    fn route_status(&self)->Option<&RouteStatus>{ self.status.as_ref() }
+    //pingora 0.1 has no h2c downstream, the grpc is served by the https listener only
+    fn allow_listener(&self,l:&Listener)->bool{
+        l.protocol == "HTTPS"
+    }
+    fn rules(&self,issues:&mut Vec<RouteIssue>)->Vec<IngRule>{
+        let namespace = self.namespace().unwrap_or_default();
+        let mut rules = vec![];
+        for r in self.spec.rules.iter(){
+            let default_match = [GRPCRouteMatch::default()];
+            let matches = if r.matches.is_empty() { &default_match[..] }else{ &r.matches[..] };
+            let mut list = vec![];
+            for m in matches.iter(){
+                match grpc_match(m) {
+                    Ok(o) => list.push(o),
+                    Err(e) => issues.push(e),
+                }
+            }
+            let mut policy = rule_policy(&r.filters,&[],issues);
+            policy.backend.protocol = Some(IngBackendProtocol::Grpc);
+            let backends = rule_backends(&r.backend_refs,namespace.as_str(),issues);
+            rules.extend(match_rules(list,policy,backends));
+        }
+        rules
+    }
+}
+
+impl GatewayRoute for TLSRoute{
+    fn route_kind(&self)->RouteKind{ RouteKind::Tls }
+    fn route_meta(&self)->&ObjectMeta{ &self.metadata }
+    fn parent_refs(&self)->&[ParentReference]{ &self.spec.parent_refs }
+    fn hostnames(&self)->&[String]{ &self.spec.hostnames }
+    fn route_status(&self)->Option<&RouteStatus>{ self.status.as_ref() }
+    //the tls stream is forwarded by the passthrough listener to the first backend
+    fn rules(&self,issues:&mut Vec<RouteIssue>)->Vec<IngRule>{
+        let namespace = self.namespace().unwrap_or_default();
+        let mut backends = vec![];
+        for r in self.spec.rules.iter(){
+            backends.extend(rule_backends(&r.backend_refs,namespace.as_str(),issues));
+        }
+        let mut policy = IngPolicy::default();
+        policy.backend.ssl_passthrough = true;
+        //the plain http requests of the host are not proxied to the tls backend
+        match_rules(vec![("/".into(),0,IngConditions::default())],policy,backends)
+    }
+    fn allow_listener(&self,l:&Listener)->bool{
+        l.protocol == "TLS" && l.tls.as_ref().and_then(|x|x.mode.as_deref()) == Some("Passthrough")
+    }
+}
+
+//the result of the gateway api resources
+#[derive(Default,Debug)]
+pub struct Translation{
+    //router host => rules
+    pub hosts:BTreeMap<String,Vec<IngRule>>,
+    //router host => namespace/name of the tls secret
+    pub certs:HashMap<String,String>,
+    //route kind and key => the status of the parents managed by this controller
+    pub statuses:BTreeMap<(RouteKind,String),Vec<RouteParentStatus>>,
+}
+
+#[derive(Default,Debug)]
+pub struct GatewayStore{
+    pub classes:HashMap<String,GatewayClass>,
+    pub gateways:HashMap<String,Gateway>,
+    pub routes:HashMap<String,HTTPRoute>,
+    pub grpc_routes:HashMap<String,GRPCRoute>,
+    pub tls_routes:HashMap<String,TLSRoute>,
+}
+
+impl GatewayStore{
+    fn gateway_of(&self,route_ns:&str,p:&ParentReference)->Option<&Gateway>{
+        if p.group.as_deref().unwrap_or(GATEWAY_GROUP) != GATEWAY_GROUP || p.kind.as_deref().unwrap_or("Gateway") != "Gateway" {
+            return None
+        }
+        let gateway = self.gateways.get(format!("{}/{}",p.namespace.as_deref().unwrap_or(route_ns),p.name).as_str())?;
+        let class = self.classes.get(gateway.spec.gateway_class_name.as_str())?;
+        if class.spec.controller_name == GATEWAY_CONTROLLER_NAME {
+            Some(gateway)
+        }else{
+            None
+        }
+    }
+    fn route(&self,kind:RouteKind,key:&str)->Option<&dyn GatewayRoute>{
+        match kind {
+            RouteKind::Http => self.routes.get(key).map(|x|x as &dyn GatewayRoute),
+            RouteKind::Grpc => self.grpc_routes.get(key).map(|x|x as &dyn GatewayRoute),
+            RouteKind::Tls => self.tls_routes.get(key).map(|x|x as &dyn GatewayRoute),
+        }
+    }
+    pub fn translate(&self)->Translation{
+        let mut tr = Translation::default();
+        let mut routes = self.routes.iter().map(|(k,v)|(k,v as &dyn GatewayRoute))
+            .chain(self.grpc_routes.iter().map(|(k,v)|(k,v as &dyn GatewayRoute)))
+            .chain(self.tls_routes.iter().map(|(k,v)|(k,v as &dyn GatewayRoute)))
+            .collect::<Vec<_>>();
+        //the oldest route wins the conflict, it is inserted last
+        routes.sort_by(|(ak,a),(bk,b)|(&b.route_meta().creation_timestamp,b.route_kind(),bk).cmp(&(&a.route_meta().creation_timestamp,a.route_kind(),ak)));
+        for (key,route) in routes{
+            let meta = route.route_meta();
+            let namespace = meta.namespace.clone().unwrap_or_default();
+            let mut issues = vec![];
+            let rules = route.rules(&mut issues);
+            let resolved = match issues.first() {
+                None => condition("ResolvedRefs",true,"ResolvedRefs","".into(),meta.generation),
+                Some(i) => condition("ResolvedRefs",false,i.reason,issues.iter().map(|x|x.msg.as_str()).collect::<Vec<_>>().join("; "),meta.generation),
+            };
+            let mut hosts = BTreeMap::new();
+            let mut parents = vec![];
+            for p in route.parent_refs().iter(){
+                let gateway = if let Some(g) = self.gateway_of(namespace.as_str(),p){ g }else{ continue };
+                let gateway_ns = gateway.namespace().unwrap_or_default();
+                let mut allowed = false;
+                //router host => served on the http port
+                let mut attached = BTreeMap::new();
+                for l in gateway.spec.listeners.iter(){
+                    if p.section_name.as_ref().map(|x|*x != l.name).unwrap_or(false) || p.port.map(|x|x != l.port).unwrap_or(false) {
+                        continue
+                    }
+                    if !route.allow_listener(l) {
+                        continue
+                    }
+                    let from = l.allowed_routes.as_ref().and_then(|x|x.namespaces.as_ref()).and_then(|x|x.from.as_deref()).unwrap_or("Same");
+                    if !(from == "All" || (from == "Same" && gateway_ns == namespace)) {
+                        continue
+                    }
+                    allowed = true;
+                    let cert = l.tls.as_ref().and_then(|x|x.certificate_refs.first()).and_then(|x|{
+                        //ReferenceGrant is not supported, so the secret should be in the namespace of gateway
+                        let ns = x.namespace.as_deref().unwrap_or(gateway_ns.as_str());
+                        if ns != gateway_ns {
+                            wd_log::log_warn_ln!("RefNotPermitted: gateway[{}/{}] listener[{}] certificateRef[{}/{}] in another namespace is not permitted",gateway_ns,gateway.name_any(),l.name,ns,x.name);
+                            return None
+                        }
+                        Some(format!("{}/{}",ns,x.name))
+                    });
+                    for h in listener_hostnames(l.hostname.as_deref(),route.hostnames()){
+                        let h = router_host(h.as_str());
+                        if let (Some(ref c),"HTTPS") = (&cert,l.protocol.as_str()){
+                            tr.certs.entry(h.clone()).or_insert(c.clone());
+                        }
+                        *attached.entry(h).or_insert(false) |= l.protocol == "HTTP";
+                    }
+                }
+                let accepted = match (allowed,attached.is_empty()) {
+                    (false,_) => condition("Accepted",false,"NotAllowedByListeners",format!("no listener of gateway[{}] allows the route",p.name),meta.generation),
+                    (true,true) => condition("Accepted",false,"NoMatchingListenerHostname","no hostname matches the listeners".into(),meta.generation),
+                    (true,false) => condition("Accepted",true,"Accepted","".into(),meta.generation),
+                };
+                for (h,plain) in attached{
+                    *hosts.entry(h).or_insert(false) |= plain;
+                }
+                parents.push(RouteParentStatus{
+                    parent_ref: p.clone(),
+                    controller_name: GATEWAY_CONTROLLER_NAME.into(),
+                    conditions: vec![accepted,resolved.clone()],
+                });
+            }
+            for (h,plain) in hosts{
+                tr.hosts.entry(h).or_default().extend(rules.iter().cloned().map(|mut x|{
+                    x.tls_only = !plain;
+                    x
+                }));
+            }
+            if !parents.is_empty() {
+                tr.statuses.insert((route.route_kind(),key.clone()),parents);
+            }
+        }
+        tr
+    }
+}
+
+//the status is written only when the conditions changed, the transition time is ignored
+fn status_changed(route:&dyn GatewayRoute,parents:&[RouteParentStatus])->bool{
+    let brief = |list:&[&RouteParentStatus]|list.iter().map(|x|{
+        (x.parent_ref.clone(),x.conditions.iter().map(|c|(c.type_.clone(),c.status.clone(),c.reason.clone(),c.message.clone(),c.observed_generation)).collect::<Vec<_>>())
+    }).collect::<Vec<_>>();
+    let old = route.route_status().map(|x|x.parents.iter().filter(|x|x.controller_name == GATEWAY_CONTROLLER_NAME).collect::<Vec<_>>()).unwrap_or_default();
+    brief(&old) != brief(&parents.iter().collect::<Vec<_>>())
+}
+
+enum GatewayEvent{
+    Class(Event<GatewayClass>),
+    Gateway(Event<Gateway>),
+    Route(Event<HTTPRoute>),
+    GrpcRoute(Event<GRPCRoute>),
+    TlsRoute(Event<TLSRoute>),
+    //the leadership of this replica changed
+    Leader,
+}
+
+fn apply_event<K:Resource+Clone>(map:&mut HashMap<String,K>,event:Event<K>){
+    match event {
+        Event::Applied(o) => {
+            map.insert(resource_key(&o),o);
+        }
+        Event::Deleted(o) => {
+            map.remove(resource_key(&o).as_str());
+        }
+        Event::Restarted(list) => {
+            *map = list.into_iter().map(|x|(resource_key(&x),x)).collect();
+        }
+    }
+}
+
+//watch GatewayClass, Gateway and the routes, the routes are sent as IngressEvent to replace the rules of hosts
+pub struct WatchGateway{
+    client:Client,
+    store:GatewayStore,
+    //router host => the rules and certificate sent last time
+    sent:HashMap<String,String>,
+}
+
+impl WatchGateway{
+    pub async fn start_watch(sender:Sender<IngressEvent>)->anyhow::Result<()>{
+        let client = Client::try_default().await?;
+        let classes = watcher(Api::<GatewayClass>::all(client.clone()),watcher::Config::default()).default_backoff().map_ok(GatewayEvent::Class).boxed();
+        let gateways = watcher(Api::<Gateway>::all(client.clone()),watcher::Config::default()).default_backoff().map_ok(GatewayEvent::Gateway).boxed();
+        let routes = watcher(Api::<HTTPRoute>::all(client.clone()),watcher::Config::default()).default_backoff().map_ok(GatewayEvent::Route).boxed();
+        let grpc_routes = watcher(Api::<GRPCRoute>::all(client.clone()),watcher::Config::default()).default_backoff().map_ok(GatewayEvent::GrpcRoute).boxed();
+        let tls_routes = watcher(Api::<TLSRoute>::all(client.clone()),watcher::Config::default()).default_backoff().map_ok(GatewayEvent::TlsRoute).boxed();
+        let leader = stream::unfold(leader::subscribe(),|mut rx|async move{
+            rx.changed().await.ok()?;
+            Some((Ok(GatewayEvent::Leader),rx))
+        }).boxed();
+        let mut watch = stream::select_all(vec![classes,gateways,routes,grpc_routes,tls_routes,leader]);
+        let mut wg = WatchGateway{client,store:GatewayStore::default(),sent:HashMap::new()};
+        tokio::spawn(async move {
+            while let Some(result) = watch.next().await{
+                match result{
+                    Ok(GatewayEvent::Class(e)) => apply_event(&mut wg.store.classes,e),
+                    Ok(GatewayEvent::Gateway(e)) => apply_event(&mut wg.store.gateways,e),
+                    Ok(GatewayEvent::Route(e)) => apply_event(&mut wg.store.routes,e),
+                    Ok(GatewayEvent::GrpcRoute(e)) => apply_event(&mut wg.store.grpc_routes,e),
+                    Ok(GatewayEvent::TlsRoute(e)) => apply_event(&mut wg.store.tls_routes,e),
+                    //the status is checked again by the new leader
+                    Ok(GatewayEvent::Leader) => {}
+                    Err(e) => {
+                        wd_log::log_error_ln!("watch gateway api event error:{:?}",e);
+                        continue
+                    }
+                };
+                let event = wg.reconcile();
+                if event.hosts.is_empty() {
+                    continue
+                }
+                if let Err(e) = sender.send(event).await{
+                    wd_log::log_error_ln!("watch gateway event to sender error:{:?}",e)
+                }
+            }
+        });
+        Ok(())
+    }
+    //the hosts changed since last time, and write the route status by the leader
+    fn reconcile(&mut self)->IngressEvent{
+        let Translation{ hosts, certs, statuses } = self.store.translate();
+        let mut changed = vec![];
+        let mut sni = IngSni::default();
+        let removed = self.sent.keys().filter(|x|!hosts.contains_key(x.as_str())).cloned().collect::<Vec<_>>();
+        for host in removed{
+            self.sent.remove(host.as_str());
+            changed.push(IngHost{host,rules:vec![]});
+        }
+        for (host,rules) in hosts{
+            let cert = certs.get(host.as_str()).cloned().unwrap_or_default();
+            let digest = format!("{}|{}",cert,serde_json::to_string(&rules).unwrap_or_default());
+            if self.sent.get(host.as_str()) == Some(&digest) {
+                continue
+            }
+            self.sent.insert(host.clone(),digest);
+            if !cert.is_empty() {
+                sni.certs.insert(host.clone(),cert);
+            }
+            changed.push(IngHost{host,rules});
+        }
+        for ((kind,key),parents) in statuses{
+            if !leader::is_leader() {
+                break
+            }
+            let changed = self.store.route(kind,key.as_str()).map(|x|status_changed(x,&parents)).unwrap_or(false);
+            if !changed {
+                continue
+            }
+            match kind {
+                RouteKind::Http => self.write_status(&self.store.routes[key.as_str()],parents),
+                RouteKind::Grpc => self.write_status(&self.store.grpc_routes[key.as_str()],parents),
+                RouteKind::Tls => self.write_status(&self.store.tls_routes[key.as_str()],parents),
+            }
+        }
+        IngressEvent{ty:4,default_backend:None,hosts:changed,sni,ing:None,reports:vec![]}
+    }
+    //the parents of other controllers are kept
+    fn write_status<K>(&self,route:&K,mut parents:Vec<RouteParentStatus>)
+        where K:GatewayRoute+Resource<DynamicType=(),Scope=NamespaceResourceScope>+Clone+DeserializeOwned+Debug+Send+'static
+    {
+        let others = route.route_status().map(|x|x.parents.iter().filter(|x|x.controller_name != GATEWAY_CONTROLLER_NAME).cloned().collect::<Vec<_>>()).unwrap_or_default();
+        parents.extend(others);
+        let api:Api<K> = Api::namespaced(self.client.clone(),route.namespace().unwrap_or_default().as_str());
+        let name = route.name_any();
+        let kind = K::kind(&());
+        let patch = serde_json::json!({"status":{"parents":parents}});
+        tokio::spawn(async move{
+            if let Err(e) = api.patch_status(name.as_str(),&PatchParams::default(),&Patch::Merge(&patch)).await{
+                wd_log::log_error_ln!("write {}[{}] status error:{}",kind,name,e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::pkg::annotation::IngBackendProtocol;
+    use crate::pkg::gateway::{Gateway, GatewayClass, GatewayStore, GRPCRoute, HTTPRoute, resource_key, RouteKind, TLSRoute};
+
+    fn store()->GatewayStore{
+        let class:GatewayClass = serde_json::from_value(serde_json::json!({
+            "metadata":{"name":"pingora"},
+            "spec":{"controllerName":"pingora.ingress/gateway-controller"}
+        })).unwrap();
+        let gateway:Gateway = serde_json::from_value(serde_json::json!({
+            "metadata":{"name":"gw","namespace":"qa"},
+            "spec":{"gatewayClassName":"pingora","listeners":[
+                {"name":"http","port":80,"protocol":"HTTP"},
+                {"name":"https","port":443,"protocol":"HTTPS","hostname":"*.test.com","tls":{"certificateRefs":[{"name":"test-tls"}]}},
+                {"name":"tls","port":8443,"protocol":"TLS","tls":{"mode":"Passthrough"}}
+            ]}
+        })).unwrap();
+        let route:HTTPRoute = serde_json::from_value(serde_json::json!({
+            "metadata":{"name":"api","namespace":"qa","generation":2},
+            "spec":{
+                "parentRefs":[{"name":"gw","sectionName":"https"},{"name":"other"}],
+                "hostnames":["api.test.com","other.com"],
+                "rules":[{
+                    "matches":[
+                        {"path":{"type":"PathPrefix","value":"/v2"},"headers":[{"name":"x-canary","value":"true"}],"method":"GET"},
+                        {"path":{"type":"RegularExpression","value":"/v2/(.*"}}
+                    ],
+                    "filters":[
+                        {"type":"RequestHeaderModifier","requestHeaderModifier":{"set":[{"name":"X-Route","value":"api"}]}},
+                        {"type":"URLRewrite","urlRewrite":{"path":{"type":"ReplacePrefixMatch","replacePrefixMatch":"/"}}}
+                    ],
+                    "backendRefs":[
+                        {"name":"api-v1","port":80,"weight":90},
+                        {"name":"api-v2","port":80,"weight":10},
+                        {"name":"api-v3","namespace":"prod","port":80}
+                    ]
+                }]
+            }
+        })).unwrap();
+        let mut store = GatewayStore::default();
+        store.classes.insert(resource_key(&class),class);
+        store.gateways.insert(resource_key(&gateway),gateway);
+        store.routes.insert(resource_key(&route),route);
+        store
+    }
+
+    #[test]
+    fn test_gateway_translate(){
+        let tr = store().translate();
+        assert_eq!(tr.hosts.keys().collect::<Vec<_>>(),vec!["api.test.com"]);
+        let rules = &tr.hosts["api.test.com"];
+        assert_eq!(rules.len(),1);
+        assert_eq!(rules[0].path,"/v2");
+        assert_eq!(rules[0].conditions.method.as_deref(),Some("GET"));
+        assert_eq!(rules[0].conditions.headers[0].name,"x-canary");
+        assert_eq!(rules[0].backends.len(),2);
+        assert_eq!(rules[0].backends[0].backend,"api-v1.qa");
+        assert_eq!(rules[0].backends[1].weight,10);
+        assert_eq!(rules[0].policy.headers.request.set.len(),1);
+        assert_eq!(tr.certs["api.test.com"],"qa/test-tls");
+        //attached to the https listener only
+        assert!(rules[0].tls_only);
+
+        let parents = &tr.statuses[&(RouteKind::Http,"qa/api".to_string())];
+        assert_eq!(parents.len(),1);
+        assert_eq!(parents[0].conditions[0].type_,"Accepted");
+        assert_eq!(parents[0].conditions[0].status,"True");
+        assert_eq!(parents[0].conditions[1].status,"False");
+        assert_eq!(parents[0].conditions[1].reason,"UnsupportedValue");
+        assert_eq!(parents[0].conditions[1].observed_generation,Some(2));
+
+        //the http listener serves the route in cleartext too
+        let mut store = store();
+        let mut route = store.routes.remove("qa/api").unwrap();
+        let mut http = route.spec.parent_refs[0].clone();
+        http.section_name = Some("http".into());
+        route.spec.parent_refs.push(http);
+        store.routes.insert(resource_key(&route),route);
+        assert!(!store.translate().hosts["api.test.com"][0].tls_only);
+    }
+
+    #[test]
+    fn test_gateway_wildcard_cert_ref(){
+        let mut store = store();
+        let mut gateway = store.gateways.remove("qa/gw").unwrap();
+        gateway.spec.listeners[1].tls.as_mut().unwrap().certificate_refs[0].namespace = Some("prod".into());
+        store.gateways.insert(resource_key(&gateway),gateway);
+        let mut route = store.routes.remove("qa/api").unwrap();
+        route.spec.hostnames = vec![];
+        store.routes.insert(resource_key(&route),route);
+        let tr = store.translate();
+        //the wildcard is its own router key, not the parent domain
+        assert_eq!(tr.hosts.keys().collect::<Vec<_>>(),vec!["*.test.com"]);
+        //the secret of another namespace is not permitted
+        assert!(tr.certs.is_empty());
+    }
+
+    #[test]
+    fn test_gateway_not_allowed(){
+        let mut store = store();
+        let mut route = store.routes.remove("qa/api").unwrap();
+        route.metadata.namespace = Some("dev".into());
+        route.spec.parent_refs[0].namespace = Some("qa".into());
+        store.routes.insert(resource_key(&route),route);
+        let tr = store.translate();
+        assert!(tr.hosts.is_empty());
+        let parents = &tr.statuses[&(RouteKind::Http,"dev/api".to_string())];
+        assert_eq!(parents[0].conditions[0].reason,"NotAllowedByListeners");
+    }
+
+    #[test]
+    fn test_gateway_grpc_tls_route(){
+        let mut store = store();
+        let grpc:GRPCRoute = serde_json::from_value(serde_json::json!({
+            "metadata":{"name":"rpc","namespace":"qa"},
+            "spec":{
+                "parentRefs":[{"name":"gw","sectionName":"https"}],
+                "hostnames":["rpc.test.com"],
+                "rules":[{
+                    "matches":[
+                        {"method":{"service":"user.UserService","method":"Get"}},
+                        {"method":{"service":"user.OrderService"},"headers":[{"name":"x-env","value":"qa"}]},
+                        {"method":{"method":"Check"}}
+                    ],
+                    "backendRefs":[{"name":"user","port":9090}]
+                }]
+            }
+        })).unwrap();
+        let tls:TLSRoute = serde_json::from_value(serde_json::json!({
+            "metadata":{"name":"mtls","namespace":"qa"},
+            "spec":{
+                "parentRefs":[{"name":"gw"}],
+                "hostnames":["mtls.test.com"],
+                "rules":[{"backendRefs":[{"name":"mtls","port":8443}]}]
+            }
+        })).unwrap();
+        store.grpc_routes.insert(resource_key(&grpc),grpc);
+        store.tls_routes.insert(resource_key(&tls),tls);
+        let tr = store.translate();
+
+        let rules = &tr.hosts["rpc.test.com"];
+        assert_eq!((rules[0].path.as_str(),rules[0].ty),("/user.UserService/Get",2));
+        assert_eq!((rules[1].path.as_str(),rules[1].ty),("/user.OrderService",1));
+        assert_eq!(rules[1].conditions.headers[0].name,"x-env");
+        assert_eq!((rules[2].path.as_str(),rules[2].ty),("/[^/]+/Check$",3));
+        assert!(rules.iter().all(|x|x.policy.backend.protocol == Some(IngBackendProtocol::Grpc)));
+        assert_eq!(rules[0].backend,"user.qa");
+        //the http listener has no h2c
+        let mut grpc = store.grpc_routes.remove("qa/rpc").unwrap();
+        grpc.spec.parent_refs[0].section_name = Some("http".into());
+        store.grpc_routes.insert(resource_key(&grpc),grpc);
+        let parents = &store.translate().statuses[&(RouteKind::Grpc,"qa/rpc".to_string())];
+        assert_eq!((parents[0].conditions[0].status.as_str(),parents[0].conditions[0].reason.as_str()),("False","NotAllowedByListeners"));
+
+        let rules = &tr.hosts["mtls.test.com"];
+        assert_eq!(rules.len(),1);
+        assert!(rules[0].policy.backend.ssl_passthrough);
+        assert_eq!(rules[0].ty,0);
+        assert_eq!((rules[0].backend.as_str(),rules[0].port),("mtls.qa",8443));
+        //the tls route is attached to the passthrough listener only
+        let parents = &tr.statuses[&(RouteKind::Tls,"qa/mtls".to_string())];
+        assert_eq!(parents[0].conditions[0].status,"True");
+    }
+}
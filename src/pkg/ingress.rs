@@ -1,36 +1,53 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use async_channel::Receiver;
 use k8s_openapi::api::networking::v1::{HTTPIngressPath, Ingress, IngressRule, IngressServiceBackend, IngressTLS};
-use kube::{Api, Client};
+use kube::{Api, Client, Resource};
 use futures::prelude::*;
 use kube::runtime::{watcher, WatchStreamExt};
 use kube::runtime::watcher::{ Event};
 use serde::{Deserialize, Serialize};
-use wd_tools::PFSome;
+use crate::pkg::annotation::IngPolicy;
+use crate::pkg::event::{EventRecorder, IngReport};
 
-const INGRESS_CLASS_NAME_PINGORA:&'static str = "pingora";
+const INGRESS_CLASS_NAME_PINGORA:&str = "pingora";
 
 #[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct IngressEvent{
-    pub ty:u8, //1:init  2:update 3:delete
+    pub ty:u8, //1:init  2:update 3:delete 4:replace
     pub default_backend:Option<IngRule>,
     pub hosts:Vec<IngHost>,
     pub sni:IngSni,
     #[serde(skip)]
     pub ing:Option<Event<Ingress>>,
+    #[serde(skip)]
+    pub reports:Vec<IngReport>,
 }
 impl IngressEvent{
-    pub fn init(mut self)->Self{
+    pub fn new(value: Event<Ingress>,owner:&mut IngPathOwner)->Self{
+        let ie = IngressEvent{
+            ty: 0,
+            default_backend: None,
+            hosts: vec![],
+            sni: Default::default(),
+            ing: Some(value),
+            reports: vec![],
+        };
+        ie.init(owner)
+    }
+    pub fn init(mut self,owner:&mut IngPathOwner)->Self{
         match self.ing.as_ref().unwrap() {
             Event::Applied(ref k) => {
-                let (default_backend,hosts,sni) = IngressEvent::ing_to_host_backend(k);
+                let (default_backend,hosts,sni,mut reports) = IngressEvent::ing_to_host_backend(k);
+                reports.append(&mut owner.check(k,&default_backend,&hosts));
                 self.ty = 2;
                 self.default_backend = default_backend;
                 self.hosts = hosts;
                 self.sni = sni;
+                self.reports = reports;
             },
             Event::Deleted(ref k) => {
-                let (default_backend,hosts,sni) = IngressEvent::ing_to_host_backend(k);
+                let (default_backend,hosts,sni,_) = IngressEvent::ing_to_host_backend(k);
+                owner.remove(k);
                 self.ty = 3;
                 self.default_backend = default_backend;
                 self.hosts = hosts;
@@ -40,52 +57,79 @@ impl IngressEvent{
                 let mut default_backend = None;
                 let mut hosts = vec![];
                 let mut sni = IngSni::default();
+                let mut reports = vec![];
+                owner.clear();
                 for i in k.iter(){
-                    let (db, mut hs,is) = IngressEvent::ing_to_host_backend(i);
+                    let (db, mut hs,is,mut rs) = IngressEvent::ing_to_host_backend(i);
+                    rs.append(&mut owner.check(i,&db,&hs));
                     if db.is_some() {
                         default_backend = db;
                     }
                     hosts.append(&mut hs);
                     sni.append(is);
+                    reports.append(&mut rs);
                 }
                 self.ty = 1;
                 self.default_backend = default_backend;
                 self.hosts = hosts;
                 self.sni = sni;
+                self.reports = reports;
             },
         };
 
         self
     }
-    pub fn ing_to_host_backend(ing:&Ingress)->(Option<IngRule>,Vec<IngHost>,IngSni){
+    pub fn ing_to_host_backend(ing:&Ingress)->(Option<IngRule>,Vec<IngHost>,IngSni,Vec<IngReport>){
         let i = if let Some(ref i) = ing.spec{
             i
         }else{
-            return (None,vec![],IngSni::default())
+            return (None,vec![],IngSni::default(),vec![])
         };
         if let Some(ref n) = i.ingress_class_name {
             if n != INGRESS_CLASS_NAME_PINGORA {
-                return (None,vec![],IngSni::default())
+                return (None,vec![],IngSni::default(),vec![])
             }
         }
+        let mut reports = vec![];
+        let policy = if let Some(ref an) = ing.metadata.annotations{
+            let (policy,errors) = IngPolicy::from_annotations(an,ing.metadata.namespace.as_deref().unwrap_or("default"));
+            for e in errors{
+                reports.push(IngReport::warning(ing.object_ref(&()),"InvalidAnnotation",e.to_string()));
+            }
+            policy
+        }else{
+            IngPolicy::default()
+        };
         let default_backend = if let Some(ref d) = i.default_backend{
-            if let Some(ref s) = d.service{Some(IngRule::from(s))}else{None}
+            d.service.as_ref().map(|s|IngRule::from(s).set_policy(policy.clone()))
         }else{None};
         let mut hosts = vec![];
+        let mut rule_count = 0;
         if let Some(ref i) = i.rules{
             for i in i.iter(){
-                let ih = IngHost::from(i);
+                let (mut ih,errors) = IngHost::new_from_rule(i);
+                for e in errors{
+                    reports.push(IngReport::warning(ing.object_ref(&()),"InvalidRule",e.to_string()));
+                }
+                for r in ih.rules.iter_mut(){
+                    r.policy = policy.clone();
+                    r.owner = IngPathOwner::ing_name(ing);
+                }
                 if !ih.rules.is_empty() {
+                    rule_count += ih.rules.len();
                     hosts.push(ih);
                 }
             }
         }
         let sni = if let Some(ref s) = i.tls{
-            IngSni::from_ingress_tls(s)
+            IngSni::from_ingress_tls(s,ing.metadata.namespace.as_deref().unwrap_or("default"))
         }else{
             IngSni::default()
         };
-        (default_backend,hosts,sni)
+        let note = format!("synced by pingora-ingress: {} host(s), {} path(s), default backend: {}",
+                           hosts.len(),rule_count,default_backend.is_some());
+        reports.push(IngReport::normal(ing.object_ref(&()),"Sync",note));
+        (default_backend,hosts,sni,reports)
     }
     pub fn json(&self)->String{
         serde_json::to_string(self).unwrap_or_else(|e| {
@@ -94,25 +138,16 @@ impl IngressEvent{
         })
     }
 }
-impl From<Event<Ingress>> for IngressEvent {
-    fn from(value: Event<Ingress>) -> Self {
-        let ie = IngressEvent{
-            ty: 0,
-            default_backend: None,
-            hosts: vec![],
-            sni: Default::default(),
-            ing: Some(value),
-        };
-        ie.init()
-    }
-}
 #[derive(Default,Debug,Clone,Serialize,Deserialize)]
 pub struct IngSni{
-    pub sni : HashMap<String,String>
+    pub sni : HashMap<String,String>,
+    //host => namespace/secret name, the certificate of the https listener
+    pub certs : HashMap<String,String>,
 }
 impl IngSni{
-    pub fn from_ingress_tls(tls:&Vec<IngressTLS>)->IngSni{
+    pub fn from_ingress_tls(tls:&[IngressTLS],namespace:&str)->IngSni{
         let mut sni = HashMap::new();
+        let mut certs = HashMap::new();
         for i in tls.iter(){
             let secret = if let Some(ref s) = i.secret_name {
                 s.clone()
@@ -122,13 +157,15 @@ impl IngSni{
             if let Some(ref hs) = i.hosts{
                 for j in hs.iter(){
                     sni.insert(j.clone(),secret.clone());
+                    certs.insert(j.clone(),format!("{}/{}",namespace,secret));
                 }
             }
         }
-        Self{sni}
+        Self{sni,certs}
     }
     pub fn append(&mut self,other:Self){
         self.sni.extend(other.sni);
+        self.certs.extend(other.certs);
     }
 }
 
@@ -138,29 +175,71 @@ pub struct IngHost{
     pub rules:Vec<IngRule>,
 }
 
-impl From<&IngressRule> for IngHost {
-    fn from(value: &IngressRule) -> Self {
+impl IngHost{
+    //invalid path will be skipped, and return the reason
+    pub fn new_from_rule(value: &IngressRule) -> (Self,Vec<anyhow::Error>) {
         let host = if let Some(ref s) = value.host{
             s.clone()
         }else{"".to_string()};
         let mut rules = vec![];
+        let mut errors = vec![];
         if let Some(ref list) = value.http{
             for p in list.paths.iter(){
-                if let Some(r) = IngRule::new_from_path(p){
-                    rules.push(r)
+                match IngRule::new_from_path(p) {
+                    Ok(r) => rules.push(r),
+                    Err(e) => {
+                        wd_log::log_warn_ln!("host[{}] skip invalid rule:{}",host,e);
+                        errors.push(anyhow::anyhow!("host[{}] {}",host,e));
+                    }
                 }
             }
         }
-        Self{host,rules}
+        (Self{host,rules},errors)
     }
 }
 
 #[derive(Default,Debug,Clone,Serialize,Deserialize)]
 pub struct IngRule{
     pub path: String,
-    pub ty:u8, //1:prefix 2:exact 3:specific
+    pub ty:u8, //1:prefix 2:exact 3:specific 0:passthrough only, no http route
     pub backend: String,
     pub port:i32,
+    #[serde(default)]
+    pub policy:IngPolicy,
+    //the conditions besides the path, from the gateway api route matches
+    #[serde(default)]
+    pub conditions:IngConditions,
+    //weighted backends, empty means the backend and port above
+    #[serde(default)]
+    pub backends:Vec<IngBackendRef>,
+    //namespace/name of the ingress, tells the canary from its primary
+    #[serde(default)]
+    pub owner:String,
+    //the gateway route attached to the https listeners only is not served on the http port
+    #[serde(default)]
+    pub tls_only:bool,
+}
+
+//all of the conditions should be matched, empty means any request
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct IngConditions{
+    pub headers:Vec<IngValueMatch>,
+    pub query:Vec<IngValueMatch>,
+    pub method:Option<String>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct IngValueMatch{
+    pub name:String,
+    pub value:String,
+    pub regex:bool,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct IngBackendRef{
+    pub backend:String,
+    pub port:i32,
+    pub weight:u32,
 }
 
 impl From<&IngressServiceBackend> for IngRule {
@@ -170,6 +249,7 @@ impl From<&IngressServiceBackend> for IngRule {
             ty: 1,
             backend: value.name.clone(),
             port: 80,
+            ..Default::default()
         };
         if let Some(ref i) = value.port{
             if let Some(i) = i.number{
@@ -180,22 +260,96 @@ impl From<&IngressServiceBackend> for IngRule {
     }
 }
 impl IngRule{
-    pub fn new_from_path(value:&HTTPIngressPath)->Option<Self>{
+    pub fn new_from_path(value:&HTTPIngressPath)->anyhow::Result<Self>{
+        let path = value.path.clone().unwrap_or("".to_string());
         let ty = match value.path_type.as_str().to_lowercase().as_str() {
             "prefix" => 1u8,
             "exact" => 2u8,
-            "implementationspecific" => 3u8,
-            _ => 99u8,
+            //the path is a regex matched from the beginning
+            "implementationspecific" => {
+                if let Err(e) = regex::Regex::new(format!("^(?:{})",path).as_str()) {
+                    return Err(anyhow::anyhow!("path[{}] is not a valid regex:{}",path,e))
+                }
+                3u8
+            }
+            _ => {
+                return Err(anyhow::anyhow!("path[{}] pathType[{}] is not supported",path,value.path_type))
+            },
         };
-        let path = value.path.clone().unwrap_or("".to_string());
         let mut this = if let Some(ref s) = value.backend.service{
             IngRule::from(s)
         }else{
-            return None
+            return Err(anyhow::anyhow!("path[{}] only service backend is supported",path))
         };
         this.ty = ty;
         this.path = path;
-        this.some()
+        Ok(this)
+    }
+    pub fn set_policy(mut self,policy:IngPolicy)->Self{
+        self.policy = policy;self
+    }
+}
+
+//the owner ingress of every host and path, used to find the conflicting rules.
+//the ingresses of a path are kept in the applied order, the last one is the owner
+#[derive(Default,Debug)]
+pub struct IngPathOwner{
+    owner:HashMap<String,Vec<String>>,
+}
+
+impl IngPathOwner{
+    fn ing_name(ing:&Ingress)->String{
+        format!("{}/{}",ing.metadata.namespace.as_deref().unwrap_or_default(),ing.metadata.name.as_deref().unwrap_or_default())
+    }
+    pub fn clear(&mut self){
+        self.owner.clear();
+    }
+    //only the ingress is removed, the path is owned by the previous one again
+    pub fn remove(&mut self,ing:&Ingress){
+        let name = IngPathOwner::ing_name(ing);
+        self.owner.retain(|_,v|{
+            v.retain(|x|*x != name);
+            !v.is_empty()
+        });
+    }
+    //register all rules of the ingress, the latest applied rule takes effect
+    pub fn check(&mut self,ing:&Ingress,default_backend:&Option<IngRule>,hosts:&[IngHost])->Vec<IngReport>{
+        self.remove(ing);
+        let name = IngPathOwner::ing_name(ing);
+        let mut keys = vec![];
+        if default_backend.is_some() {
+            keys.push(("*".to_string(),"defaultBackend".to_string()));
+        }
+        for h in hosts.iter(){
+            for r in h.rules.iter(){
+                let ty = match r.ty {
+                    2 => "Exact",
+                    3 => "ImplementationSpecific",
+                    _ => "Prefix",
+                };
+                //the canary does not conflict with its primary
+                let canary = if r.policy.canary.is_some() {"(canary)"}else{""};
+                keys.push((h.host.clone(),format!("{}:{}{}",ty,r.path,canary)));
+            }
+        }
+        let mut reports = vec![];
+        let mut current = HashSet::new();
+        for (host,path) in keys{
+            let key = format!("{}|{}",host,path);
+            if !current.insert(key.clone()) {
+                let note = format!("host[{}] path[{}] is duplicated in this ingress, the last one takes effect",host,path);
+                reports.push(IngReport::warning(ing.object_ref(&()),"PathConflict",note));
+                continue
+            }
+            let owners = self.owner.entry(key).or_default();
+            let other = owners.last().cloned();
+            owners.push(name.clone());
+            if let Some(other) = other{
+                let note = format!("host[{}] path[{}] conflicts with ingress[{}], the latest applied one takes effect",host,path,other);
+                reports.push(IngReport::warning(ing.object_ref(&()),"PathConflict",note));
+            }
+        }
+        reports
     }
 }
 
@@ -223,6 +377,7 @@ impl WatchIngress {
 
 
         let client = Client::try_default().await?;
+        let recorder = EventRecorder::new(client.clone());
         let api:Api<Ingress> = match self.namespace {
             None =>{
                 Api::all(client)
@@ -230,6 +385,7 @@ impl WatchIngress {
             Some(ref s) => Api::namespaced(client,s)
         };
 
+        let mut owner = IngPathOwner::default();
         let mut wc = watcher::Config::default();
         if let Some(ref s) = self.selector_labels{
             wc = wc.labels(s)
@@ -246,7 +402,8 @@ impl WatchIngress {
                         continue
                     }
                 };
-                let event = IngressEvent::from(event);
+                let mut event = IngressEvent::new(event,&mut owner);
+                recorder.publish_all(std::mem::take(&mut event.reports));
                 if let Err(e) = sender.send(event).await{
                     wd_log::log_error_ln!("watch ingress event to sender error:{:?}",e)
                 }
@@ -264,4 +421,66 @@ impl WatchIngress {
         Ok(receiver)
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test{
+    use k8s_openapi::api::networking::v1::Ingress;
+    use crate::pkg::ingress::{IngPathOwner, IngressEvent};
+
+    fn ingress(name:&str,path:&str,path_type:&str)->Ingress{
+        serde_json::from_value(serde_json::json!({
+            "metadata":{"name":name,"namespace":"qa"},
+            "spec":{
+                "ingressClassName":"pingora",
+                "rules":[{"host":"test.com","http":{"paths":[
+                    {"path":path,"pathType":path_type,"backend":{"service":{"name":"echo","port":{"number":80}}}},
+                    {"path":"/res","pathType":"Prefix","backend":{"resource":{"kind":"Bucket","name":"bk"}}}
+                ]}}]
+            }
+        })).unwrap()
+    }
+
+    #[test]
+    fn test_invalid_and_conflict_rules(){
+        let (_,hosts,_,reports) = IngressEvent::ing_to_host_backend(&ingress("a","/api","Prefix"));
+        assert_eq!(hosts[0].rules.len(),1);
+        let reasons = reports.iter().map(|x|x.reason.as_str()).collect::<Vec<_>>();
+        assert_eq!(reasons,vec!["InvalidRule","Sync"]);
+
+        let (_,_,_,reports) = IngressEvent::ing_to_host_backend(&ingress("a","/api","Regex"));
+        assert_eq!(reports.iter().filter(|x|x.reason == "InvalidRule").count(),2);
+        let (_,hosts,_,_) = IngressEvent::ing_to_host_backend(&ingress("a","/api/(.*)","ImplementationSpecific"));
+        assert_eq!(hosts[0].rules[0].ty,3);
+        let (_,_,_,reports) = IngressEvent::ing_to_host_backend(&ingress("a","/api/(.*","ImplementationSpecific"));
+        assert_eq!(reports.iter().filter(|x|x.reason == "InvalidRule").count(),2);
+
+        let mut owner = IngPathOwner::default();
+        let a = ingress("a","/api","Prefix");
+        let (db,hosts,_,_) = IngressEvent::ing_to_host_backend(&a);
+        assert!(owner.check(&a,&db,&hosts).is_empty());
+        assert!(owner.check(&a,&db,&hosts).is_empty());
+
+        let b = ingress("b","/api","Prefix");
+        let reports = owner.check(&b,&db,&hosts);
+        assert_eq!(reports.len(),1);
+        assert!(reports[0].note.contains("qa/a"));
+
+        owner.remove(&b);
+        let c = ingress("c","/api","Exact");
+        let (db,hosts,_,_) = IngressEvent::ing_to_host_backend(&c);
+        assert!(owner.check(&c,&db,&hosts).is_empty());
+
+        //the path is still owned by a after b is deleted
+        let (db,hosts,_,_) = IngressEvent::ing_to_host_backend(&a);
+        let d = ingress("d","/api","Prefix");
+        let reports = owner.check(&d,&db,&hosts);
+        assert_eq!(reports.len(),1);
+        assert!(reports[0].note.contains("qa/a"));
+        //deleting a keeps the entry of d
+        owner.remove(&a);
+        let reports = owner.check(&b,&db,&hosts);
+        assert_eq!(reports.len(),1);
+        assert!(reports[0].note.contains("qa/d"));
+    }
+}
@@ -0,0 +1,159 @@
+use async_channel::Receiver;
+use futures::prelude::*;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
+use k8s_openapi::chrono::Utc;
+use kube::{Api, Client, CustomResource, ResourceExt};
+use kube::api::{Patch, PatchParams};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::runtime::watcher::Event;
+use serde::{Deserialize, Serialize};
+use crate::pkg::leader;
+
+//the gateway-wide settings, the fields not set are from the pod annotations or the default
+#[derive(CustomResource,Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[kube(group="pingora.ingress",version="v1alpha1",kind="PingoraIngressConfig",namespaced,status="PingoraIngressConfigStatus",schema="disabled")]
+#[serde(rename_all="camelCase")]
+pub struct PingoraIngressConfigSpec{
+    pub log_level:Option<String>,
+    #[serde(default)]
+    pub listen:ListenConfig,
+    #[serde(default)]
+    pub timeouts:TimeoutConfig,
+    #[serde(default)]
+    pub default_headers:DefaultHeaders,
+    #[serde(default)]
+    pub tls:TlsDefaults,
+}
+
+//the listeners are bound at start, the changes take effect after restart
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct ListenConfig{
+    pub port:Option<i32>,
+    pub https_port:Option<i32>,
+    pub passthrough_port:Option<i32>,
+    pub metrics_port:Option<i32>,
+}
+
+//unit: second
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct TimeoutConfig{
+    pub proxy_connect:Option<u64>,
+    pub proxy_read:Option<u64>,
+    pub proxy_send:Option<u64>,
+    pub proxy_idle:Option<u64>,
+    pub websocket_idle:Option<u64>,
+    pub udp_session:Option<u64>,
+    pub auth:Option<u64>,
+    pub jwks_cache_ttl:Option<u64>,
+}
+
+//the headers of all routes, they are applied before the headers of the route
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct DefaultHeaders{
+    #[serde(default)]
+    pub request:HeaderModifier,
+    #[serde(default)]
+    pub response:HeaderModifier,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct HeaderModifier{
+    #[serde(default)]
+    pub set:Vec<HeaderValue>,
+    #[serde(default)]
+    pub add:Vec<HeaderValue>,
+    #[serde(default)]
+    pub remove:Vec<String>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+pub struct HeaderValue{
+    pub name:String,
+    pub value:String,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct TlsDefaults{
+    //negotiate http/2 with alpn on the https listener
+    pub http2:Option<bool>,
+    //namespace/name of the tls secret used when no host of the sni has a certificate
+    pub default_certificate:Option<String>,
+}
+
+#[derive(Default,Debug,Clone,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all="camelCase")]
+pub struct PingoraIngressConfigStatus{
+    #[serde(default)]
+    pub conditions:Vec<Condition>,
+}
+
+fn api_of(client:Client,key:&str)->anyhow::Result<(Api<PingoraIngressConfig>,String)>{
+    let (namespace,name) = key.split_once('/').ok_or_else(||anyhow::anyhow!("PingoraIngressConfig[{}] should be namespace/name",key))?;
+    Ok((Api::namespaced(client,namespace),name.to_string()))
+}
+
+pub async fn get_ingress_config(key:&str)->anyhow::Result<Option<PingoraIngressConfig>>{
+    let client = Client::try_default().await?;
+    let (api,name) = api_of(client,key)?;
+    Ok(api.get_opt(name.as_str()).await?)
+}
+
+//watch one PingoraIngressConfig by namespace/name, none is sent when it is deleted
+pub async fn watch_ingress_config(key:&str)->anyhow::Result<Receiver<Option<PingoraIngressConfig>>>{
+    let client = Client::try_default().await?;
+    let (api,name) = api_of(client,key)?;
+    let (sender,receiver) = async_channel::bounded(2);
+    let wc = watcher::Config::default().fields(format!("metadata.name={}",name).as_str());
+    let mut watch = watcher(api, wc).default_backoff().boxed();
+    let key = key.to_string();
+    tokio::spawn(async move {
+        while let Some(result) = watch.next().await{
+            let cfg = match result{
+                Ok(Event::Applied(o)) => Some(o),
+                Ok(Event::Deleted(_)) => None,
+                Ok(Event::Restarted(list)) => list.into_iter().next(),
+                Err(e) => {
+                    wd_log::log_error_ln!("watch PingoraIngressConfig[{}] event error:{:?}",key,e);
+                    continue
+                }
+            };
+            if let Err(e) = sender.send(cfg).await{
+                wd_log::log_error_ln!("PingoraIngressConfig[{}] send error:{}",key,e);
+                return
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+//the validation result of the config, written by the leader only when it changed
+pub async fn write_status(cfg:&PingoraIngressConfig,ok:bool,reason:&str,msg:String)->anyhow::Result<()>{
+    if !leader::is_leader() {
+        return Ok(())
+    }
+    let old = cfg.status.as_ref().and_then(|x|x.conditions.iter().find(|x|x.type_ == "Accepted"));
+    let status = if ok {"True"}else{"False"};
+    if let Some(c) = old {
+        if c.status == status && c.reason == reason && c.message == msg && c.observed_generation == cfg.metadata.generation {
+            return Ok(())
+        }
+    }
+    let condition = Condition{
+        last_transition_time: Time(Utc::now()),
+        message: msg,
+        observed_generation: cfg.metadata.generation,
+        reason: reason.into(),
+        status: status.into(),
+        type_: "Accepted".into(),
+    };
+    let client = Client::try_default().await?;
+    let api:Api<PingoraIngressConfig> = Api::namespaced(client,cfg.namespace().unwrap_or_default().as_str());
+    let patch = serde_json::json!({"status":{"conditions":[condition]}});
+    api.patch_status(cfg.name_any().as_str(),&PatchParams::default(),&Patch::Merge(&patch)).await?;
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta};
+use k8s_openapi::chrono::Utc;
+use kube::{Api, Client};
+use kube::api::PostParams;
+use tokio::sync::watch;
+use crate::pkg::pod::PodApi;
+
+//all replicas are the leader when the election is disabled
+fn leader_state()->&'static watch::Sender<bool>{
+    static LEADER:OnceLock<watch::Sender<bool>> = OnceLock::new();
+    LEADER.get_or_init(||watch::channel(true).0)
+}
+
+//only the leader writes the status and events, all replicas serve the traffic
+pub fn is_leader()->bool{
+    *leader_state().borrow()
+}
+
+pub fn subscribe()->watch::Receiver<bool>{
+    leader_state().subscribe()
+}
+
+fn set_leader(leader:bool){
+    leader_state().send_if_modified(|x|{
+        if *x == leader {
+            return false
+        }
+        wd_log::log_info_ln!("leader election: {}",if leader {"became the leader"}else{"lost the leadership"});
+        *x = leader;
+        true
+    });
+}
+
+//unit: second
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct LeaseTiming{
+    //a follower takes over after the lease is not renewed in the duration
+    pub lease_duration:u64,
+    //the leader gives up when it can not renew in the deadline
+    pub renew_deadline:u64,
+    pub retry_period:u64,
+}
+
+impl LeaseTiming{
+    //the leader should give up before the followers take over
+    pub fn check(&self)->anyhow::Result<()>{
+        if self.retry_period == 0 || self.renew_deadline <= self.retry_period || self.lease_duration <= self.renew_deadline {
+            return Err(anyhow::anyhow!("leader election expect lease_duration > renew_deadline > retry_period > 0, found {:?}",self))
+        }
+        Ok(())
+    }
+}
+
+//the holder and renew time of the lease, they are compared with the local clock of the last change to avoid the clock skew
+#[derive(Debug,Clone,PartialEq,Default)]
+struct LeaseRecord{
+    holder:String,
+    renew_time:Option<MicroTime>,
+    transitions:i32,
+}
+
+impl From<&Lease> for LeaseRecord{
+    fn from(value: &Lease) -> Self {
+        let spec = value.spec.clone().unwrap_or_default();
+        Self{
+            holder: spec.holder_identity.unwrap_or_default(),
+            renew_time: spec.renew_time,
+            transitions: spec.lease_transitions.unwrap_or_default(),
+        }
+    }
+}
+
+pub struct LeaderElection{
+    api:Api<Lease>,
+    name:String,
+    identity:String,
+    timing:LeaseTiming,
+    observed:LeaseRecord,
+    observed_at:Instant,
+    //the last time the leader renewed
+    renewed_at:Option<Instant>,
+}
+
+impl LeaderElection{
+    //lease is namespace/name of the coordination.k8s.io lease.
+    //the replica is not the leader from now on, also when the election fails to start
+    pub async fn start(lease:&str,timing:LeaseTiming)->anyhow::Result<()>{
+        set_leader(false);
+        timing.check()?;
+        let (namespace,name) = lease.split_once('/').ok_or_else(||anyhow::anyhow!("lease[{}] should be namespace/name",lease))?;
+        let identity = PodApi::pod_name();
+        if identity.is_empty() {
+            return Err(anyhow::anyhow!("leader election identity(pod name) not found"))
+        }
+        let client = Client::try_default().await?;
+        let mut le = Self{
+            api: Api::namespaced(client,namespace),
+            name: name.to_string(),
+            identity,
+            timing,
+            observed: LeaseRecord::default(),
+            observed_at: Instant::now(),
+            renewed_at: None,
+        };
+        tokio::spawn(async move{
+            loop {
+                let leader = match le.try_acquire_or_renew().await {
+                    Ok(o) => o,
+                    Err(e) => {
+                        wd_log::log_debug_ln!("leader election lease[{}] error:{}",le.name,e);
+                        false
+                    }
+                };
+                if leader {
+                    le.renewed_at = Some(Instant::now());
+                    set_leader(true);
+                }else if le.renewed_at.map(|x|x.elapsed() >= Duration::from_secs(le.timing.renew_deadline)).unwrap_or(true) {
+                    le.renewed_at = None;
+                    set_leader(false);
+                }
+                tokio::time::sleep(Duration::from_secs(le.timing.retry_period)).await;
+            }
+        });
+        Ok(())
+    }
+    fn lease_spec(&self,transitions:i32,acquire:bool,old:Option<LeaseSpec>)->LeaseSpec{
+        let now = MicroTime(Utc::now());
+        let old = old.unwrap_or_default();
+        LeaseSpec{
+            holder_identity: Some(self.identity.clone()),
+            lease_duration_seconds: Some(self.timing.lease_duration as i32),
+            acquire_time: if acquire { Some(now.clone()) }else{ old.acquire_time },
+            renew_time: Some(now),
+            lease_transitions: Some(transitions),
+        }
+    }
+    async fn try_acquire_or_renew(&mut self)->anyhow::Result<bool>{
+        let lease = match self.api.get_opt(self.name.as_str()).await? {
+            Some(o) => o,
+            None => {
+                let lease = Lease{
+                    metadata: ObjectMeta{name:Some(self.name.clone()),..Default::default()},
+                    spec: Some(self.lease_spec(0,true,None)),
+                };
+                self.api.create(&PostParams::default(),&lease).await?;
+                return Ok(true)
+            }
+        };
+        let record = LeaseRecord::from(&lease);
+        if record != self.observed {
+            self.observed = record.clone();
+            self.observed_at = Instant::now();
+        }
+        let held_by_me = record.holder == self.identity;
+        let expired = self.observed_at.elapsed() >= Duration::from_secs(self.timing.lease_duration);
+        if !held_by_me && !record.holder.is_empty() && !expired {
+            return Ok(false)
+        }
+        //the resource version makes the update fail when another replica updated it first
+        let transitions = if held_by_me { record.transitions }else{ record.transitions + 1 };
+        let spec = self.lease_spec(transitions,!held_by_me,lease.spec.clone());
+        let lease = Lease{spec:Some(spec),..lease};
+        self.api.replace(self.name.as_str(),&PostParams::default(),&lease).await?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::pkg::leader::LeaseTiming;
+
+    #[test]
+    fn test_lease_timing(){
+        assert!(LeaseTiming{lease_duration:15,renew_deadline:10,retry_period:2}.check().is_ok());
+        assert!(LeaseTiming{lease_duration:10,renew_deadline:10,retry_period:2}.check().is_err());
+        assert!(LeaseTiming{lease_duration:15,renew_deadline:10,retry_period:0}.check().is_err());
+    }
+}
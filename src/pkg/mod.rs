@@ -1,2 +1,9 @@
 pub mod ingress;
-pub mod pod;
\ No newline at end of file
+pub mod pod;
+pub mod annotation;
+pub mod event;
+pub mod secret;
+pub mod configmap;
+pub mod gateway;
+pub mod ingress_config;
+pub mod leader;
\ No newline at end of file
@@ -54,7 +54,7 @@ impl PodApi{
 
 #[cfg(test)]
 mod test{
-    use k8s_openapi::api::core::v1::{Namespace, Pod};
+    use k8s_openapi::api::core::v1::Pod;
     use kube::{Api, Client};
 
     #[tokio::test]
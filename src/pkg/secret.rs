@@ -0,0 +1,165 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, OnceLock};
+use futures::prelude::*;
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client};
+use kube::runtime::{watcher, WatchStreamExt};
+use kube::runtime::watcher::Event;
+use wd_tools::sync::Acl;
+use crate::infra::cert::TlsCert;
+use crate::infra::htpasswd::Htpasswd;
+use crate::infra::jwt::Jwks;
+
+const SECRET_KEY_HTPASSWD:&str = "auth";
+const SECRET_KEY_JWKS:&str = "jwks.json";
+const SECRET_KEY_TLS_CRT:&str = "tls.crt";
+const SECRET_KEY_TLS_KEY:&str = "tls.key";
+const SECRET_TYPES:[&str;2] = ["Opaque","kubernetes.io/tls"];
+//the opaque secrets of the auth and jwks should be labeled, the others of the cluster are not read
+const SECRET_OPAQUE_LABEL:&str = "pingora.ingress/secret=true";
+
+//a version of secret, the parsed content is cached until the secret changed
+#[derive(Debug,Default)]
+pub struct SecretEntry{
+    pub ty:String,
+    pub data:BTreeMap<String,Vec<u8>>,
+    htpasswd:OnceLock<Option<Arc<Htpasswd>>>,
+    jwks:OnceLock<Option<Arc<Jwks>>>,
+    tls:OnceLock<Option<Arc<TlsCert>>>,
+}
+
+impl SecretEntry{
+    pub fn get_str(&self,key:&str)->Option<&str>{
+        self.data.get(key).and_then(|x|std::str::from_utf8(x).ok())
+    }
+    //htpasswd file in the key `auth`
+    pub fn htpasswd(&self)->Option<Arc<Htpasswd>>{
+        self.htpasswd.get_or_init(||{
+            let content = self.get_str(SECRET_KEY_HTPASSWD)?;
+            let (ht,invalid) = Htpasswd::parse(content);
+            if !invalid.is_empty() {
+                wd_log::log_warn_ln!("secret htpasswd skip invalid users:{:?}",invalid);
+            }
+            Some(Arc::new(ht))
+        }).clone()
+    }
+    //json web key set in the key `jwks.json`
+    pub fn jwks(&self)->Option<Arc<Jwks>>{
+        self.jwks.get_or_init(||{
+            let content = self.get_str(SECRET_KEY_JWKS)?;
+            match Jwks::parse(content) {
+                Ok(o) => Some(Arc::new(o)),
+                Err(e) => {
+                    wd_log::log_warn_ln!("secret parse jwks failed:{}",e);
+                    None
+                }
+            }
+        }).clone()
+    }
+    //certificate and private key in the keys `tls.crt` and `tls.key`
+    pub fn tls(&self)->Option<Arc<TlsCert>>{
+        self.tls.get_or_init(||{
+            let crt = self.data.get(SECRET_KEY_TLS_CRT)?;
+            let key = self.data.get(SECRET_KEY_TLS_KEY)?;
+            match TlsCert::parse(crt,key) {
+                Ok(o) => Some(Arc::new(o)),
+                Err(e) => {
+                    wd_log::log_warn_ln!("secret parse tls certificate failed:{}",e);
+                    None
+                }
+            }
+        }).clone()
+    }
+}
+
+impl From<&Secret> for SecretEntry{
+    fn from(value: &Secret) -> Self {
+        let mut data = BTreeMap::new();
+        if let Some(ref d) = value.data{
+            for (k,v) in d.iter(){
+                data.insert(k.clone(),v.0.clone());
+            }
+        }
+        if let Some(ref d) = value.string_data{
+            for (k,v) in d.iter(){
+                data.insert(k.clone(),v.as_bytes().to_vec());
+            }
+        }
+        let ty = value.type_.clone().unwrap_or_default();
+        Self{ty,data,..Default::default()}
+    }
+}
+
+//opaque and tls secrets of the cluster, key is namespace/name
+#[derive(Clone,Default)]
+pub struct SecretStore{
+    store:Acl<HashMap<String,Arc<SecretEntry>>>,
+}
+
+impl SecretStore{
+    fn secret_key(s:&Secret)->String{
+        format!("{}/{}",s.metadata.namespace.as_deref().unwrap_or_default(),s.metadata.name.as_deref().unwrap_or_default())
+    }
+    pub fn get(&self,key:&str)->Option<Arc<SecretEntry>>{
+        self.store.share().get(key).cloned()
+    }
+    fn on_event(&self,ty:&str,event:Event<Secret>){
+        match event {
+            Event::Applied(s) => {
+                let key = SecretStore::secret_key(&s);
+                wd_log::log_debug_ln!("update secret[{}]",key);
+                let entry = Arc::new(SecretEntry::from(&s));
+                self.store.update(move |x|{
+                    let mut map = (*x).clone();
+                    map.insert(key,entry);
+                    map
+                });
+            }
+            Event::Deleted(s) => {
+                let key = SecretStore::secret_key(&s);
+                wd_log::log_debug_ln!("delete secret[{}]",key);
+                self.store.update(move |x|{
+                    let mut map = (*x).clone();
+                    map.remove(key.as_str());
+                    map
+                });
+            }
+            Event::Restarted(list) => {
+                //each type has its own watcher, only the secrets of this type are reloaded
+                let list = list.iter().map(|s|(SecretStore::secret_key(s),Arc::new(SecretEntry::from(s)))).collect::<Vec<_>>();
+                wd_log::log_debug_ln!("reload secret type[{}] count[{}]",ty,list.len());
+                let ty = ty.to_string();
+                self.store.update(move |x|{
+                    let mut map = (*x).clone();
+                    map.retain(|_,v|v.ty != ty);
+                    map.extend(list);
+                    map
+                });
+            }
+        }
+    }
+    //watch the opaque and tls secrets, changes take effect without restart
+    pub async fn start_watch(self)->anyhow::Result<Self>{
+        let client = Client::try_default().await?;
+        for ty in SECRET_TYPES{
+            let api:Api<Secret> = Api::all(client.clone());
+            let mut wc = watcher::Config::default().fields(format!("type={}",ty).as_str());
+            if ty == "Opaque" {
+                wc = wc.labels(SECRET_OPAQUE_LABEL);
+            }
+            let mut watch = watcher(api, wc).default_backoff().boxed();
+            let store = self.clone();
+            tokio::spawn(async move {
+                while let Some(result) = watch.next().await{
+                    match result{
+                        Ok(o) => store.on_event(ty,o),
+                        Err(e) => {
+                            wd_log::log_error_ln!("watch secret[{}] event error:{:?}",ty,e);
+                        }
+                    };
+                }
+            });
+        }
+        Ok(self)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,54 @@
+use pingora::prelude::*;
+use crate::service::http_proxy::HttpProxyCtx;
+use crate::service::response::write_response;
+
+//return true if the client ip is denied and 403 has been responded
+pub async fn access_filter(session:&mut Session,ctx:&HttpProxyCtx)->Result<bool>{
+    let node = if let Some(ref s) = ctx.service{ s }else{ return Ok(false) };
+    let access = &node.policy.access;
+    if access.invalid {
+        wd_log::log_warn_ln!("access of host[{}] path[{}] is invalid, deny all",node.host,node.path);
+        return write_response(session,403,vec![],None).await
+    }
+    if access.allow.is_empty() && access.deny.is_empty() {
+        return Ok(false)
+    }
+    let allowed = match ctx.client_ip {
+        Some(ref ip) => !access.deny.contains(ip) && (access.allow.is_empty() || access.allow.contains(ip)),
+        None => access.allow.is_empty(), //unix socket peer
+    };
+    if allowed {
+        return Ok(false)
+    }
+    wd_log::log_debug_ln!("client[{:?}] is denied by ingress host[{}] path[{}]",ctx.client_ip,node.host,node.path);
+    write_response(session,403,vec![],None).await
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use crate::pkg::annotation::IngPolicy;
+    use crate::pkg::ingress::IngRule;
+    use crate::service::http_proxy::test::{request, start_proxy};
+
+    //an invalid range denies all clients instead of being ignored
+    #[tokio::test]
+    async fn test_invalid_range_denied(){
+        let cases = [
+            ("allowlist-source-range","10.0.0.0/33"),
+            ("allowlist-source-range","127.0.0.1,intranet"),
+            ("denylist-source-range","10.0.0.x"),
+        ];
+        for (i,(key,value)) in cases.iter().enumerate(){
+            let an = BTreeMap::from([(format!("pingora.ingress/{}",key),value.to_string())]);
+            let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+            assert_eq!(errors.len(),1);
+            assert!(policy.access.invalid);
+            let host = format!("access{}.test.com",i);
+            let rule = IngRule{path:"/".into(),ty:1,backend:"127.0.0.1".into(),port:1,..Default::default()}.set_policy(policy);
+            let addr = start_proxy(Default::default(),host.as_str(),vec![rule]).await;
+            let resp = request(addr,format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n",host).as_str()).await;
+            assert!(resp.starts_with("http/1.1 403"),"{}",resp);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use pingora::prelude::*;
+use crate::pkg::secret::SecretStore;
+use crate::service::http_proxy::HttpProxyCtx;
+use crate::service::response::write_response;
+
+pub struct BasicAuthFilter{
+    secrets:SecretStore,
+}
+
+impl BasicAuthFilter{
+    pub fn new(secrets:SecretStore)->Self{
+        Self{secrets}
+    }
+    //return true if the credentials are invalid and 401 has been responded
+    pub async fn filter(&self,session:&mut Session,ctx:&HttpProxyCtx)->Result<bool>{
+        let node = if let Some(ref s) = ctx.service{ s }else{ return Ok(false) };
+        let auth = if let Some(ref s) = node.policy.basic_auth{ s }else{ return Ok(false) };
+
+        let htpasswd = self.secrets.get(auth.secret.as_str()).and_then(|x|x.htpasswd());
+        let credentials = session.req_header().headers.get("Authorization")
+            .and_then(|x|x.to_str().ok())
+            .and_then(parse_basic_credentials);
+        let passed = match (htpasswd,credentials) {
+            (Some(ht),Some((user,password))) => {
+                //bcrypt is too slow to run in the async task
+                tokio::task::spawn_blocking(move ||ht.verify(user.as_str(),password.as_str())).await.unwrap_or(false)
+            }
+            (None,_) if auth.secret.is_empty() => {
+                wd_log::log_warn_ln!("basic auth of host[{}] path[{}] is invalid, deny all",node.host,node.path);
+                false
+            }
+            (None,_) => {
+                wd_log::log_warn_ln!("basic auth secret[{}] not found or no `auth` key, deny all",auth.secret);
+                false
+            }
+            _ => false,
+        };
+        if passed {
+            return Ok(false)
+        }
+        let challenge = format!("Basic realm=\"{}\"",auth.realm.replace('"',""));
+        write_response(session,401,vec![("WWW-Authenticate".into(),challenge)],None).await
+    }
+}
+
+fn parse_basic_credentials(value:&str)->Option<(String,String)>{
+    let (scheme,token) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None
+    }
+    let raw = STANDARD.decode(token.trim()).ok()?;
+    let raw = String::from_utf8(raw).ok()?;
+    let (user,password) = raw.split_once(':')?;
+    Some((user.to_string(),password.to_string()))
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use crate::pkg::annotation::IngPolicy;
+    use crate::pkg::ingress::IngRule;
+    use crate::service::auth::parse_basic_credentials;
+    use crate::service::http_proxy::test::{request, start_proxy};
+
+    #[test]
+    fn test_parse_basic_credentials(){
+        assert_eq!(parse_basic_credentials("Basic Ym9iOjEyMzo0NTY="),Some(("bob".into(),"123:456".into())));
+        assert_eq!(parse_basic_credentials("basic  Ym9iOjEyMzo0NTY="),Some(("bob".into(),"123:456".into())));
+        assert_eq!(parse_basic_credentials("Bearer Ym9iOjEyMzo0NTY="),None);
+        assert_eq!(parse_basic_credentials("Basic xxx"),None);
+    }
+
+    //the invalid auth annotations deny the requests instead of leaving the route open
+    #[tokio::test]
+    async fn test_invalid_basic_auth_denied(){
+        let cases = [
+            vec![("auth-type","basic"),("auth-secret","kube-system/users")],
+            vec![("auth-type","digest"),("auth-secret","users")],
+            vec![("auth-secret","users")],
+            vec![("auth-type","basic")],
+        ];
+        for (i,case) in cases.iter().enumerate(){
+            let an = case.iter().map(|(k,v)|(format!("pingora.ingress/{}",k),v.to_string())).collect::<BTreeMap<_,_>>();
+            let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+            assert!(!errors.is_empty());
+            let host = format!("auth{}.test.com",i);
+            let rule = IngRule{path:"/".into(),ty:1,backend:"127.0.0.1".into(),port:1,..Default::default()}.set_policy(policy);
+            let addr = start_proxy(Default::default(),host.as_str(),vec![rule]).await;
+            let resp = request(addr,format!("GET / HTTP/1.1\r\nHost: {}\r\nAuthorization: Basic Ym9iOjEyMzQ1Ng==\r\n\r\n",host).as_str()).await;
+            assert!(resp.starts_with("http/1.1 401"),"{}",resp);
+        }
+    }
+}
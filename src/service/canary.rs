@@ -0,0 +1,231 @@
+use std::sync::Arc;
+use pingora::http::RequestHeader;
+use rand::Rng;
+use regex::Regex;
+use crate::pkg::annotation::IngCanary;
+use crate::pkg::ingress::IngConditions;
+use crate::service::http_proxy::RouterNode;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Decision{
+    Canary,
+    Primary,
+    //ask the next rule
+    Next,
+}
+
+//the canary ingress overlaid on a primary node, the pattern is compiled once.
+//the canary is served with the annotations of the primary like nginx-ingress, only the backends are its own
+#[derive(Debug,Clone)]
+pub struct CanaryNode{
+    rule:IngCanary,
+    pattern:Option<Regex>,
+    //the canary node as applied
+    source:Arc<RouterNode>,
+    pub node:Arc<RouterNode>,
+}
+
+impl CanaryNode{
+    //none when the node is not a canary
+    pub fn new(primary:&RouterNode,source:Arc<RouterNode>)->Option<Arc<Self>>{
+        let rule = source.policy.canary.clone()?;
+        //the regex has been checked when the annotation is parsed
+        let pattern = rule.by_header_pattern.as_ref().and_then(|x|Regex::new(x.as_str()).ok());
+        let node = RouterNode{
+            backend: source.backend.clone(),
+            port: source.port,
+            backends: source.backends.clone(),
+            alternatives: vec![],
+            canary: None,
+            owner: source.owner.clone(),
+            ..primary.clone()
+        };
+        Some(Arc::new(Self{rule,pattern,source,node:Arc::new(node)}))
+    }
+    fn by_header(&self,c:&IngCanary,req:&RequestHeader)->Decision{
+        let value = match c.by_header.as_ref().and_then(|x|req.headers.get(x.as_str())).and_then(|x|x.to_str().ok()) {
+            Some(o) => o,
+            None => return Decision::Next,
+        };
+        if let Some(ref v) = c.by_header_value {
+            return if v == value {Decision::Canary}else{Decision::Next}
+        }
+        if let Some(ref r) = self.pattern {
+            return if r.is_match(value) {Decision::Canary}else{Decision::Next}
+        }
+        always_or_never(value)
+    }
+    fn by_cookie(&self,c:&IngCanary,req:&RequestHeader)->Decision{
+        let name = match c.by_cookie {
+            Some(ref o) => o.as_str(),
+            None => return Decision::Next,
+        };
+        let value = req.headers.get_all(http::header::COOKIE).iter()
+            .filter_map(|x|x.to_str().ok())
+            .flat_map(|x|x.split(';'))
+            .filter_map(|x|x.trim().split_once('='))
+            .find(|(k,_)|*k == name)
+            .map(|(_,v)|v);
+        value.map(always_or_never).unwrap_or(Decision::Next)
+    }
+    fn by_weight(&self,c:&IngCanary)->bool{
+        c.weight > 0 && rand::thread_rng().gen_range(0..c.weight_total.max(1)) < c.weight
+    }
+    //the header first, then the cookie, then the weight
+    fn is_selected(&self,req:&RequestHeader)->bool{
+        let c = &self.rule;
+        for d in [self.by_header(c,req),self.by_cookie(c,req)]{
+            match d {
+                Decision::Canary => return true,
+                Decision::Primary => return false,
+                Decision::Next => {}
+            }
+        }
+        self.by_weight(c)
+    }
+}
+
+fn always_or_never(value:&str)->Decision{
+    match value {
+        "always" => Decision::Canary,
+        "never" => Decision::Primary,
+        _ => Decision::Next,
+    }
+}
+
+//the canary of the node when the request is selected, else the node itself
+pub fn select_canary(node:Arc<RouterNode>,req:&RequestHeader)->Arc<RouterNode>{
+    match node.canary {
+        Some(ref c) if c.is_selected(req) => c.node.clone(),
+        _ => node,
+    }
+}
+
+//the new node overlays the old node of the same conditions.
+//a canary without primary serves all requests with its own annotations until the primary is applied
+pub fn overlay(mut old:RouterNode,mut new:RouterNode)->RouterNode{
+    match (old.policy.canary.is_some(),new.policy.canary.is_some()) {
+        (false,true) => {
+            old.canary = CanaryNode::new(&old,Arc::new(new));
+            old
+        }
+        (true,false) => {
+            if !same_owner(&old,&new) {
+                new.canary = CanaryNode::new(&new,Arc::new(old));
+            }
+            new
+        }
+        (false,false) => {
+            //the canary ingress without the canary annotation replaces the primary
+            new.canary = old.canary.take()
+                .filter(|x|!same_owner(&x.source,&new))
+                .and_then(|x|CanaryNode::new(&new,x.source.clone()));
+            new
+        }
+        (true,true) => new,
+    }
+}
+
+fn same_owner(a:&RouterNode,b:&RouterNode)->bool{
+    !a.owner.is_empty() && a.owner == b.owner
+}
+
+//the node without the canary of the conditions, none when no primary holds the canary
+pub fn remove_canary(node:&Arc<RouterNode>,conditions:&IngConditions)->Option<Arc<RouterNode>>{
+    let mut node = (**node).clone();
+    if node.conditions == *conditions {
+        node.canary.take()?;
+        return Some(Arc::new(node))
+    }
+    let i = node.alternatives.iter().position(|x|x.conditions == *conditions)?;
+    let mut alt = (*node.alternatives[i]).clone();
+    alt.canary.take()?;
+    node.alternatives[i] = Arc::new(alt);
+    Some(Arc::new(node))
+}
+
+#[cfg(test)]
+mod test{
+    use std::sync::Arc;
+    use pingora::http::RequestHeader;
+    use crate::infra::ip::CidrSet;
+    use crate::pkg::annotation::IngCanary;
+    use crate::pkg::ingress::IngRule;
+    use crate::service::canary::{overlay, remove_canary, select_canary};
+    use crate::service::http_proxy::RouterNode;
+
+    fn node(backend:&str,canary:Option<IngCanary>)->RouterNode{
+        let mut rule = IngRule{path:"/api".into(),ty:1,backend:backend.into(),port:80,owner:format!("qa/{}",backend),..Default::default()};
+        rule.policy.canary = canary;
+        RouterNode::from(rule)
+    }
+
+    fn request(headers:&[(&str,&str)])->RequestHeader{
+        let mut req = RequestHeader::build("GET","/api".as_bytes(),None).unwrap();
+        for (k,v) in headers{
+            req.insert_header(k.to_string(),v.to_string()).unwrap();
+        }
+        req
+    }
+
+    #[test]
+    fn test_canary_select(){
+        let canary = IngCanary{
+            by_header:Some("x-canary".into()),
+            by_cookie:Some("canary".into()),
+            weight_total:100,
+            ..Default::default()
+        };
+        let n = Arc::new(overlay(node("v1",None),node("v2",Some(canary))));
+        let backend = |h:&[(&str,&str)]|select_canary(n.clone(),&request(h)).backend.clone();
+        assert_eq!(backend(&[]),"v1");
+        assert_eq!(backend(&[("x-canary","always")]),"v2");
+        assert_eq!(backend(&[("x-canary","never"),("cookie","canary=always")]),"v1");
+        assert_eq!(backend(&[("x-canary","other"),("cookie","a=b; canary=always")]),"v2");
+        assert_eq!(backend(&[("cookie","canary=never")]),"v1");
+
+        let value = IngCanary{by_header:Some("x-canary".into()),by_header_value:Some("beta".into()),weight:100,weight_total:100,..Default::default()};
+        let n = Arc::new(overlay(node("v1",None),node("v2",Some(value))));
+        assert_eq!(select_canary(n.clone(),&request(&[("x-canary","never")])).backend,"v2");
+        assert_eq!(select_canary(n.clone(),&request(&[])).backend,"v2");
+    }
+
+    #[test]
+    fn test_canary_overlay(){
+        let canary = Some(IngCanary{weight:100,weight_total:100,..Default::default()});
+        //the canary applied first serves until the primary is applied
+        let n = overlay(node("v2",canary.clone()),node("v1",None));
+        assert_eq!(n.canary.as_ref().map(|x|x.node.backend.as_str()),Some("v2"));
+        //the update of the primary keeps the canary
+        let n = Arc::new(overlay(n,node("v3",None)));
+        assert_eq!(n.backend,"v3");
+        assert_eq!(select_canary(n.clone(),&request(&[])).backend,"v2");
+        let n = remove_canary(&n,&Default::default()).unwrap();
+        assert!(n.canary.is_none());
+        assert!(remove_canary(&n,&Default::default()).is_none());
+
+        //the canary ingress without canary: true becomes the primary without the stale canary
+        let n = overlay(node("v1",None),node("v2",canary));
+        let n = overlay(n,node("v2",None));
+        assert_eq!(n.backend,"v2");
+        assert!(n.canary.is_none());
+    }
+
+    #[test]
+    fn test_canary_inherit_policy(){
+        let mut primary = node("v1",None);
+        primary.policy.access.allow = CidrSet::parse(&["10.0.0.0/8"]).0;
+        let canary = node("v2",Some(IngCanary{by_header:Some("x-canary".into()),weight_total:100,..Default::default()}));
+        let n = Arc::new(overlay(primary,canary));
+        let c = select_canary(n.clone(),&request(&[("x-canary","always")]));
+        assert_eq!(c.backend,"v2");
+        assert_eq!(c.policy.access.allow,n.policy.access.allow);
+        assert!(c.policy.canary.is_none());
+        //the update of the primary is applied to the canary
+        let mut primary = node("v1",None);
+        primary.policy.timeout.read = Some(3);
+        let n = Arc::new(overlay((*n).clone(),primary));
+        let c = select_canary(n.clone(),&request(&[("x-canary","always")]));
+        assert_eq!((c.backend.as_str(),c.policy.timeout.read),("v2",Some(3)));
+    }
+}
@@ -1,18 +1,166 @@
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
-use crate::pkg::pod;
+use wd_tools::sync::Acl;
+use crate::pkg::annotation::{HEADER_VARIABLES, IngHeaderRules, IngHeaders};
+use crate::pkg::ingress_config::{self, HeaderModifier, PingoraIngressConfigSpec};
+use crate::pkg::{leader, pod};
+use crate::pkg::leader::LeaseTiming;
 
-#[derive(Debug,Serialize,Deserialize)]
+#[derive(Debug,Clone,Serialize,Deserialize)]
 pub struct Config{
     #[serde(default="Config::port_df")]
     pub port:i32,
+    //https listener, the certificate is selected by sni from the ingress tls. 0 means disable
+    #[serde(default="Config::https_port_df")]
+    pub https_port:i32,
+    //the tls listener for ssl passthrough, the other hosts are forwarded to the https listener. 0 means disable
+    #[serde(default="i32::default")]
+    pub passthrough_port:i32,
+    //negotiate http/2 with alpn on the https listener
+    #[serde(default="Config::http2_df")]
+    pub http2:bool,
+    //prometheus metrics listener, 0 means disable
+    #[serde(default="Config::metrics_port_df")]
+    pub metrics_port:i32,
     #[serde(default="String::default")]
     pub log_level:String,
+    //upstream timeout, unit: second, 0 means use pingora default
+    #[serde(default="Config::proxy_connect_timeout_df")]
+    pub proxy_connect_timeout:u64,
+    #[serde(default="Config::proxy_read_timeout_df")]
+    pub proxy_read_timeout:u64,
+    #[serde(default="Config::proxy_send_timeout_df")]
+    pub proxy_send_timeout:u64,
+    #[serde(default="Config::proxy_idle_timeout_df")]
+    pub proxy_idle_timeout:u64,
+    //idle timeout of the upgraded connection, unit: second
+    #[serde(default="Config::websocket_idle_timeout_df")]
+    pub websocket_idle_timeout:u64,
+    //the client ip is read from real_ip_header only when the peer is in trusted_proxies
+    #[serde(default="Vec::default")]
+    pub trusted_proxies:Vec<String>,
+    #[serde(default="Config::real_ip_header_df")]
+    pub real_ip_header:String,
+    //add rfc7239 Forwarded header to upstream request
+    #[serde(default="bool::default")]
+    pub forwarded_header:bool,
+    //timeout of the external auth subrequest, unit: second
+    #[serde(default="Config::auth_timeout_df")]
+    pub auth_timeout:u64,
+    //timeout of the mirror request, unit: second
+    #[serde(default="Config::mirror_timeout_df")]
+    pub mirror_timeout:u64,
+    //the https port of the clients in the Location of ssl-redirect, omitted when 443
+    #[serde(default="Config::ssl_redirect_port_df")]
+    pub ssl_redirect_port:u64,
+    //the mirror requests in flight, a new mirror request is dropped over it
+    #[serde(default="Config::mirror_max_concurrency_df")]
+    pub mirror_max_concurrency:u64,
+    //watch the gateway api resources of the GatewayClass with controller pingora.ingress/gateway-controller
+    #[serde(default="bool::default")]
+    pub gateway_api:bool,
+    //nginx-ingress style tcp-services and udp-services, namespace/name of the configmap, empty means disable
+    #[serde(default="String::default")]
+    pub tcp_services_configmap:String,
+    #[serde(default="String::default")]
+    pub udp_services_configmap:String,
+    //a udp client without datagrams in the timeout is released, unit: second
+    #[serde(default="Config::udp_session_timeout_df")]
+    pub udp_session_timeout:u64,
+    //the udp clients of one port, the datagrams of new clients are dropped over it
+    #[serde(default="Config::udp_max_sessions_df")]
+    pub udp_max_sessions:u64,
+    //the jwks from url or file is reloaded after the ttl, unit: second
+    #[serde(default="Config::jwks_cache_ttl_df")]
+    pub jwks_cache_ttl:u64,
+    //namespace/name of the PingoraIngressConfig, it overrides the pod annotations and is watched. empty means disable
+    #[serde(default="String::default")]
+    pub ingress_config:String,
+    //the headers of all routes
+    #[serde(default="IngHeaders::default")]
+    pub default_headers:IngHeaders,
+    //namespace/name of the tls secret used when the sni has no certificate
+    #[serde(default="String::default")]
+    pub default_certificate:String,
+    //namespace/name of the lease, only the leader writes the status and events. empty means every replica writes
+    #[serde(default="String::default")]
+    pub leader_election_lease:String,
+    //the failover timing of leader election, unit: second
+    #[serde(default="Config::leader_lease_duration_df")]
+    pub leader_lease_duration:u64,
+    #[serde(default="Config::leader_renew_deadline_df")]
+    pub leader_renew_deadline:u64,
+    #[serde(default="Config::leader_retry_period_df")]
+    pub leader_retry_period:u64,
+}
+
+impl Default for Config{
+    fn default() -> Self {
+        serde_json::from_str::<Config>("{}").unwrap()
+    }
 }
 
 impl Config{
     fn port_df()->i32{
         30666
     }
+    fn https_port_df()->i32{
+        30443
+    }
+    fn http2_df()->bool{
+        true
+    }
+    fn metrics_port_df()->i32{
+        30667
+    }
+    fn proxy_connect_timeout_df()->u64{
+        5
+    }
+    fn proxy_read_timeout_df()->u64{
+        60
+    }
+    fn proxy_send_timeout_df()->u64{
+        60
+    }
+    fn proxy_idle_timeout_df()->u64{
+        60
+    }
+    fn websocket_idle_timeout_df()->u64{
+        3600
+    }
+    fn auth_timeout_df()->u64{
+        5
+    }
+    fn mirror_timeout_df()->u64{
+        5
+    }
+    fn ssl_redirect_port_df()->u64{
+        443
+    }
+    fn mirror_max_concurrency_df()->u64{
+        256
+    }
+    fn jwks_cache_ttl_df()->u64{
+        300
+    }
+    fn udp_max_sessions_df()->u64{
+        1024
+    }
+    fn udp_session_timeout_df()->u64{
+        60
+    }
+    fn leader_lease_duration_df()->u64{
+        15
+    }
+    fn leader_renew_deadline_df()->u64{
+        10
+    }
+    fn leader_retry_period_df()->u64{
+        2
+    }
+    fn real_ip_header_df()->String{
+        "X-Forwarded-For".into()
+    }
     pub fn json(&self)->String{
         serde_json::to_string(self).unwrap()
     }
@@ -21,7 +169,7 @@ impl Config{
 
 impl Config{
     pub async fn from_pod()->Self{
-        let mut cfg = serde_json::from_str::<Config>("{}").unwrap();
+        let mut cfg = Config::default();
         let pod = match pod::PodApi::get_self_pod_info().await {
             Ok(o) => o,
             Err(e) => {
@@ -33,6 +181,34 @@ impl Config{
             if let Some(level) = an.get("pga-log-level"){
                 cfg.log_level = level.to_string();
             }
+            cfg.load_timeout_from_annotations(an);
+            if let Some(s) = an.get("pga-trusted-proxies"){
+                cfg.trusted_proxies = s.split(',').map(|x|x.trim().to_string()).filter(|x|!x.is_empty()).collect();
+            }
+            if let Some(s) = an.get("pga-real-ip-header"){
+                cfg.real_ip_header = s.trim().to_string();
+            }
+            if let Some(s) = an.get("pga-forwarded-header"){
+                cfg.forwarded_header = s.trim() == "true";
+            }
+            if let Some(s) = an.get("pga-tcp-services-configmap"){
+                cfg.tcp_services_configmap = s.trim().to_string();
+            }
+            if let Some(s) = an.get("pga-udp-services-configmap"){
+                cfg.udp_services_configmap = s.trim().to_string();
+            }
+            if let Some(s) = an.get("pga-gateway-api"){
+                cfg.gateway_api = s.trim() == "true";
+            }
+            if let Some(s) = an.get("pga-http2"){
+                cfg.http2 = s.trim() != "false";
+            }
+            if let Some(s) = an.get("pga-ingress-config"){
+                cfg.ingress_config = s.trim().to_string();
+            }
+            if let Some(s) = an.get("pga-leader-election-lease"){
+                cfg.leader_election_lease = s.trim().to_string();
+            }
         };
         if let Some(ref spec) = pod.spec{
             for i in spec.containers.iter(){
@@ -41,6 +217,12 @@ impl Config{
                         if let Some(ref n) = j.name{
                             if n=="http"{
                                 cfg.port = j.container_port
+                            }else if n=="https"{
+                                cfg.https_port = j.container_port
+                            }else if n=="passthrough"{
+                                cfg.passthrough_port = j.container_port
+                            }else if n=="metrics"{
+                                cfg.metrics_port = j.container_port
                             }
                         }
                     }
@@ -49,4 +231,269 @@ impl Config{
         }
         cfg
     }
-}
\ No newline at end of file
+    fn load_timeout_from_annotations(&mut self,an:&BTreeMap<String,String>){
+        let list = [
+            ("pga-proxy-connect-timeout",&mut self.proxy_connect_timeout),
+            ("pga-proxy-read-timeout",&mut self.proxy_read_timeout),
+            ("pga-proxy-send-timeout",&mut self.proxy_send_timeout),
+            ("pga-proxy-idle-timeout",&mut self.proxy_idle_timeout),
+            ("pga-websocket-idle-timeout",&mut self.websocket_idle_timeout),
+            ("pga-auth-timeout",&mut self.auth_timeout),
+            ("pga-mirror-timeout",&mut self.mirror_timeout),
+            ("pga-mirror-max-concurrency",&mut self.mirror_max_concurrency),
+            ("pga-ssl-redirect-port",&mut self.ssl_redirect_port),
+            ("pga-jwks-cache-ttl",&mut self.jwks_cache_ttl),
+            ("pga-udp-session-timeout",&mut self.udp_session_timeout),
+            ("pga-udp-max-sessions",&mut self.udp_max_sessions),
+            ("pga-leader-lease-duration",&mut self.leader_lease_duration),
+            ("pga-leader-renew-deadline",&mut self.leader_renew_deadline),
+            ("pga-leader-retry-period",&mut self.leader_retry_period),
+        ];
+        for (key,field) in list{
+            if let Some(s) = an.get(key){
+                match s.trim().parse::<u64>() {
+                    Ok(o) => *field = o,
+                    Err(e) => {
+                        wd_log::log_warn_ln!("pod annotation[{}={}] parse failed:{}",key,s,e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+const LOG_LEVELS:[&str;5] = ["panic","error","warn","info","debug"];
+
+fn header_rules(m:&HeaderModifier,ty:&str,errors:&mut Vec<String>)->IngHeaderRules{
+    let mut rules = IngHeaderRules::default();
+    for name in m.remove.iter(){
+        if http::HeaderName::from_bytes(name.as_bytes()).is_ok() {
+            rules.remove.push(name.clone());
+        }else{
+            errors.push(format!("defaultHeaders.{}.remove: invalid header name {}",ty,name));
+        }
+    }
+    for (field,list,out) in [("set",&m.set,&mut rules.set),("add",&m.add,&mut rules.add)]{
+        for h in list.iter(){
+            let mut rest = h.value.clone();
+            for v in HEADER_VARIABLES.iter(){
+                rest = rest.replace(v,"");
+            }
+            if http::HeaderName::from_bytes(h.name.as_bytes()).is_err() {
+                errors.push(format!("defaultHeaders.{}.{}: invalid header name {}",ty,field,h.name));
+            }else if rest.contains("${") {
+                errors.push(format!("defaultHeaders.{}.{}: unknown variable in {}, expect one of {:?}",ty,field,h.value,HEADER_VARIABLES));
+            }else if http::HeaderValue::from_str(rest.as_str()).is_err() {
+                errors.push(format!("defaultHeaders.{}.{}: invalid header value {}",ty,field,h.value));
+            }else{
+                out.push((h.name.clone(),h.value.clone()));
+            }
+        }
+    }
+    rules
+}
+
+//the settings of PingoraIngressConfig
+impl Config{
+    //the config with the spec applied, nothing is applied when any field is invalid
+    pub fn with_spec(&self,spec:&PingoraIngressConfigSpec)->Result<Config,Vec<String>>{
+        let mut cfg = self.clone();
+        let mut errors = vec![];
+        if let Some(ref level) = spec.log_level {
+            if LOG_LEVELS.contains(&level.to_lowercase().as_str()) {
+                cfg.log_level = level.clone();
+            }else{
+                errors.push(format!("logLevel: expect one of {:?}, found {}",LOG_LEVELS,level));
+            }
+        }
+        let listen = &spec.listen;
+        let ports = [
+            ("listen.port",listen.port,&mut cfg.port),
+            ("listen.httpsPort",listen.https_port,&mut cfg.https_port),
+            ("listen.passthroughPort",listen.passthrough_port,&mut cfg.passthrough_port),
+            ("listen.metricsPort",listen.metrics_port,&mut cfg.metrics_port),
+        ];
+        for (key,value,field) in ports{
+            match value {
+                //the http listener can not be disabled
+                Some(p) if !(0..=65535).contains(&p) || (p == 0 && key == "listen.port") => errors.push(format!("{}: invalid port {}",key,p)),
+                Some(p) => *field = p,
+                None => {}
+            }
+        }
+        let t = &spec.timeouts;
+        let timeouts = [
+            (t.proxy_connect,&mut cfg.proxy_connect_timeout),
+            (t.proxy_read,&mut cfg.proxy_read_timeout),
+            (t.proxy_send,&mut cfg.proxy_send_timeout),
+            (t.proxy_idle,&mut cfg.proxy_idle_timeout),
+            (t.websocket_idle,&mut cfg.websocket_idle_timeout),
+            (t.udp_session,&mut cfg.udp_session_timeout),
+            (t.auth,&mut cfg.auth_timeout),
+            (t.jwks_cache_ttl,&mut cfg.jwks_cache_ttl),
+        ];
+        for (value,field) in timeouts{
+            if let Some(v) = value {
+                *field = v;
+            }
+        }
+        let request = header_rules(&spec.default_headers.request,"request",&mut errors);
+        let response = header_rules(&spec.default_headers.response,"response",&mut errors);
+        if !request.is_empty() || !response.is_empty() {
+            cfg.default_headers = IngHeaders{request,response};
+        }
+        if let Some(h2) = spec.tls.http2 {
+            cfg.http2 = h2;
+        }
+        match spec.tls.default_certificate {
+            Some(ref s) if s.split_once('/').map(|(ns,name)|ns.is_empty() || name.is_empty()).unwrap_or(true) => {
+                errors.push(format!("tls.defaultCertificate: expect namespace/name, found {}",s));
+            }
+            Some(ref s) => cfg.default_certificate = s.clone(),
+            None => {}
+        }
+        if errors.is_empty() {
+            Ok(cfg)
+        }else{
+            Err(errors)
+        }
+    }
+    //the fields changed from the running config, they take effect after restart
+    pub fn restart_fields(&self,running:&Config)->Vec<&'static str>{
+        let list = [
+            ("listen.port",self.port != running.port),
+            ("listen.httpsPort",self.https_port != running.https_port),
+            ("listen.passthroughPort",self.passthrough_port != running.passthrough_port),
+            ("listen.metricsPort",self.metrics_port != running.metrics_port),
+            ("tls.http2",self.http2 != running.http2),
+            ("timeouts.auth",self.auth_timeout != running.auth_timeout),
+            ("timeouts.jwksCacheTtl",self.jwks_cache_ttl != running.jwks_cache_ttl),
+        ];
+        list.into_iter().filter(|x|x.1).map(|x|x.0).collect()
+    }
+    pub fn lease_timing(&self)->LeaseTiming{
+        LeaseTiming{
+            lease_duration: self.leader_lease_duration,
+            renew_deadline: self.leader_renew_deadline,
+            retry_period: self.leader_retry_period,
+        }
+    }
+    pub fn apply_log_level(&self){
+        if !self.log_level.is_empty() {
+            wd_log::set_level(self.log_level.as_str().into());
+        }
+    }
+    //the PingoraIngressConfig applied on the config of pod annotations at start
+    pub async fn load_ingress_config(&self)->Self{
+        let base = self.clone();
+        if base.ingress_config.is_empty() {
+            return base
+        }
+        let ic = match ingress_config::get_ingress_config(base.ingress_config.as_str()).await {
+            Ok(Some(o)) => o,
+            Ok(None) => {
+                wd_log::log_warn_ln!("PingoraIngressConfig[{}] not found",base.ingress_config);
+                return base
+            }
+            Err(e) => {
+                wd_log::log_error_ln!("load PingoraIngressConfig[{}] failed:{}",base.ingress_config,e);
+                return base
+            }
+        };
+        let (cfg,ok,reason,msg) = match base.with_spec(&ic.spec) {
+            Ok(cfg) => (cfg,true,"Accepted",String::new()),
+            Err(errors) => (base,false,"InvalidValue",errors.join("; ")),
+        };
+        if let Err(e) = ingress_config::write_status(&ic,ok,reason,msg).await{
+            wd_log::log_error_ln!("write PingoraIngressConfig[{}] status error:{}",cfg.ingress_config,e);
+        }
+        cfg
+    }
+    //the changes of PingoraIngressConfig are applied to the live config, the deleted config falls back to the pod annotations.
+    //the status is written again when this replica becomes the leader
+    pub async fn start_watch(base:Config,live:Acl<Config>)->anyhow::Result<()>{
+        let running = live.share();
+        let recv = ingress_config::watch_ingress_config(running.ingress_config.as_str()).await?;
+        let mut leader = leader::subscribe();
+        tokio::spawn(async move{
+            let mut last = None;
+            loop {
+                let ic = tokio::select! {
+                    ic = recv.recv() => match ic {
+                        Ok(o) => o,
+                        Err(_) => break,
+                    },
+                    changed = leader.changed() => {
+                        if changed.is_err() || !*leader.borrow_and_update() || last.is_none() {
+                            continue
+                        }
+                        last.clone()
+                    }
+                };
+                last = ic.clone();
+                let ic = if let Some(s) = ic{ s }else{
+                    wd_log::log_info_ln!("PingoraIngressConfig[{}] deleted, use the pod annotations",running.ingress_config);
+                    live.set(base.clone());
+                    base.apply_log_level();
+                    continue
+                };
+                let (ok,reason,msg) = match base.with_spec(&ic.spec) {
+                    Ok(cfg) => {
+                        let restart = cfg.restart_fields(&running);
+                        cfg.apply_log_level();
+                        wd_log::log_info_ln!("PingoraIngressConfig[{}] applied=>{}",running.ingress_config,cfg.json());
+                        live.set(cfg);
+                        if restart.is_empty() {
+                            (true,"Accepted",String::new())
+                        }else{
+                            (true,"RestartRequired",format!("{} take effect after restart",restart.join(",")))
+                        }
+                    }
+                    Err(errors) => {
+                        wd_log::log_warn_ln!("PingoraIngressConfig[{}] invalid:{:?}",running.ingress_config,errors);
+                        (false,"InvalidValue",errors.join("; "))
+                    }
+                };
+                if let Err(e) = ingress_config::write_status(&ic,ok,reason,msg).await{
+                    wd_log::log_error_ln!("write PingoraIngressConfig[{}] status error:{}",running.ingress_config,e);
+                }
+            }
+            wd_log::log_info_ln!("PingoraIngressConfig receiver channel over");
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use crate::pkg::ingress_config::PingoraIngressConfigSpec;
+    use crate::service::config::Config;
+
+    #[test]
+    fn test_config_with_spec(){
+        let base = Config::default();
+        let spec:PingoraIngressConfigSpec = serde_json::from_value(serde_json::json!({
+            "logLevel":"debug",
+            "listen":{"httpsPort":8443},
+            "timeouts":{"proxyRead":120},
+            "defaultHeaders":{"response":{"set":[{"name":"X-Request-ID","value":"${request_id}"}]}},
+            "tls":{"defaultCertificate":"qa/default-tls"}
+        })).unwrap();
+        let cfg = base.with_spec(&spec).unwrap();
+        assert_eq!(cfg.log_level,"debug");
+        assert_eq!(cfg.proxy_read_timeout,120);
+        assert_eq!(cfg.proxy_connect_timeout,base.proxy_connect_timeout);
+        assert_eq!(cfg.default_headers.response.set[0].0,"X-Request-ID");
+        assert_eq!(cfg.default_certificate,"qa/default-tls");
+        assert_eq!(cfg.restart_fields(&base),vec!["listen.httpsPort"]);
+
+        let spec:PingoraIngressConfigSpec = serde_json::from_value(serde_json::json!({
+            "logLevel":"trace",
+            "listen":{"port":0},
+            "defaultHeaders":{"request":{"add":[{"name":"X-Test","value":"${unknown}"}]}},
+            "tls":{"defaultCertificate":"default-tls"}
+        })).unwrap();
+        assert_eq!(base.with_spec(&spec).unwrap_err().len(),4);
+    }
+}
+
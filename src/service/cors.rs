@@ -0,0 +1,124 @@
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use crate::pkg::annotation::IngCors;
+use crate::service::http_proxy::HttpProxyCtx;
+use crate::service::response::write_response;
+
+fn request_origin(session:&Session)->Option<String>{
+    session.req_header().headers.get("Origin").and_then(|x|x.to_str().ok()).map(|x|x.to_string())
+}
+
+//answer the preflight request directly, the upstream will not receive it
+pub async fn cors_preflight(session:&mut Session,ctx:&HttpProxyCtx)->Result<bool>{
+    let cors = if let Some(s) = ctx.service.as_ref().and_then(|x|x.policy.cors.as_ref()){ s }else{ return Ok(false) };
+    let req = session.req_header();
+    if req.method != "OPTIONS" || !req.headers.contains_key("Access-Control-Request-Method") {
+        return Ok(false)
+    }
+    let origin = if let Some(s) = request_origin(session){ s }else{ return Ok(false) };
+    let mut headers = vec![];
+    if let Some(allow) = allow_origin(cors,origin.as_str()){
+        headers = cors_headers(cors,allow);
+        headers.push(("Access-Control-Allow-Methods".into(),cors.allow_methods.clone()));
+        headers.push(("Access-Control-Allow-Headers".into(),cors.allow_headers.clone()));
+        headers.push(("Access-Control-Max-Age".into(),cors.max_age.to_string()));
+    }
+    write_response(session,204,headers,None).await
+}
+
+//add Access-Control-* headers to the response of the cross-origin request
+pub fn set_cors_headers(session:&Session,resp:&mut ResponseHeader,ctx:&HttpProxyCtx)->Result<()>{
+    let cors = if let Some(s) = ctx.service.as_ref().and_then(|x|x.policy.cors.as_ref()){ s }else{ return Ok(()) };
+    let origin = if let Some(s) = request_origin(session){ s }else{ return Ok(()) };
+    if let Some(allow) = allow_origin(cors,origin.as_str()){
+        for (k,v) in cors_headers(cors,allow){
+            if k == "Vary" {
+                resp.append_header(k,v)?;
+            }else{
+                resp.insert_header(k,v)?;
+            }
+        }
+        if !cors.expose_headers.is_empty() {
+            resp.insert_header("Access-Control-Expose-Headers",cors.expose_headers.clone())?;
+        }
+    }
+    Ok(())
+}
+
+fn cors_headers(cors:&IngCors,allow:String)->Vec<(String,String)>{
+    let mut headers = vec![];
+    if allow != "*" {
+        headers.push(("Vary".into(),"Origin".into()));
+    }
+    headers.push(("Access-Control-Allow-Origin".into(),allow));
+    if cors.allow_credentials {
+        headers.push(("Access-Control-Allow-Credentials".into(),"true".into()));
+    }
+    headers
+}
+
+//the value of Access-Control-Allow-Origin, `*` can not be used with credentials
+fn allow_origin(cors:&IngCors,origin:&str)->Option<String>{
+    if cors.allow_origin.iter().any(|x|x == "*") && !cors.allow_credentials {
+        return Some("*".into())
+    }
+    if cors.allow_origin.iter().any(|x|wildcard_match(x.as_str(),origin)) {
+        Some(origin.to_string())
+    }else{
+        None
+    }
+}
+
+//`*` matches any characters
+fn wildcard_match(pattern:&str,s:&str)->bool{
+    let parts = pattern.split('*').collect::<Vec<_>>();
+    if parts.len() == 1 {
+        return pattern.eq_ignore_ascii_case(s)
+    }
+    let s = s.to_ascii_lowercase();
+    let first = parts[0].to_ascii_lowercase();
+    let last = parts[parts.len()-1].to_ascii_lowercase();
+    if s.len() < first.len() + last.len() || !s.starts_with(first.as_str()) || !s.ends_with(last.as_str()) {
+        return false
+    }
+    let mut rest = &s[first.len()..s.len()-last.len()];
+    for p in parts[1..parts.len()-1].iter(){
+        let p = p.to_ascii_lowercase();
+        match rest.find(p.as_str()) {
+            Some(i) => rest = &rest[i+p.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use crate::pkg::annotation::IngPolicy;
+    use crate::service::cors::{allow_origin, wildcard_match};
+
+    #[test]
+    fn test_cors_origin(){
+        assert!(wildcard_match("https://*.test.com","https://a.b.test.com"));
+        assert!(wildcard_match("https://*.test.com","HTTPS://A.test.com"));
+        assert!(!wildcard_match("https://*.test.com","https://test.com"));
+        assert!(!wildcard_match("https://*.test.com","http://a.test.com"));
+        assert!(wildcard_match("https://*.test.*","https://a.test.io"));
+        assert!(wildcard_match("https://test.com","https://test.com"));
+
+        let mut an = BTreeMap::new();
+        an.insert("pingora.ingress/enable-cors".to_string(),"true".to_string());
+        let (policy,_) = IngPolicy::from_annotations(&an,"qa");
+        let cors = policy.cors.unwrap();
+        assert_eq!(allow_origin(&cors,"https://a.com"),Some("*".into()));
+
+        an.insert("pingora.ingress/cors-allow-origin".to_string(),"https://*.test.com, https://test.com".to_string());
+        an.insert("pingora.ingress/cors-allow-credentials".to_string(),"true".to_string());
+        let (policy,_) = IngPolicy::from_annotations(&an,"qa");
+        let cors = policy.cors.unwrap();
+        assert_eq!(allow_origin(&cors,"https://a.test.com"),Some("https://a.test.com".into()));
+        assert_eq!(allow_origin(&cors,"https://test.com"),Some("https://test.com".into()));
+        assert_eq!(allow_origin(&cors,"https://a.com"),None);
+    }
+}
@@ -0,0 +1,150 @@
+use std::time::Duration;
+use bytes::Bytes;
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::*;
+use crate::infra::http_client::HttpClient;
+use crate::service::http_proxy::HttpProxyCtx;
+use crate::service::response::write_response;
+
+const AUTH_RESPONSE_BODY_MAX:usize = 64 * 1024;
+
+pub struct ExtAuthFilter{
+    client:HttpClient,
+}
+
+impl ExtAuthFilter{
+    pub fn new(timeout:Duration)->Self{
+        Self{client:HttpClient::new(timeout)}
+    }
+    //send the subrequest to auth service, return true if the request is denied and responded
+    pub async fn filter(&self,session:&mut Session,ctx:&mut HttpProxyCtx)->Result<bool>{
+        let node = if let Some(ref s) = ctx.service{ s.clone() }else{ return Ok(false) };
+        let auth = if let Some(ref s) = node.policy.ext_auth{ s }else{ return Ok(false) };
+        let url = if let Some(ref s) = auth.url{ s }else{
+            wd_log::log_warn_ln!("ext auth of host[{}] path[{}] is invalid, deny all",node.host,node.path);
+            return write_response(session,500,vec![],None).await
+        };
+
+        let req = auth_request(session.req_header(),&auth.request_headers,ctx)?;
+        let resp = match self.client.send(url,req,None,AUTH_RESPONSE_BODY_MAX).await {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_error_ln!("ext auth[{}] request failed:{}",url,e);
+                return write_response(session,500,vec![],None).await
+            }
+        };
+        let code = resp.header.status.as_u16();
+        match code {
+            200..=299 => {
+                ctx.auth_headers = copy_headers(&resp.header,&auth.response_headers);
+                Ok(false)
+            }
+            401 | 403 => {
+                let headers = resp.header.headers.iter()
+                    .filter(|(k,_)|!HOP_HEADERS.contains(&k.as_str()))
+                    .filter_map(|(k,v)|Some((k.to_string(),v.to_str().ok()?.to_string())))
+                    .collect();
+                write_response(session,code,headers,Some(resp.body)).await
+            }
+            _ => {
+                wd_log::log_warn_ln!("ext auth[{}] unexpected status:{}",url,code);
+                write_response(session,500,vec![],None).await
+            }
+        }
+    }
+}
+
+pub const HOP_HEADERS:[&str;6] = ["connection","keep-alive","transfer-encoding","content-length","upgrade","te"];
+
+fn auth_request(origin:&RequestHeader,names:&[String],ctx:&HttpProxyCtx)->Result<RequestHeader>{
+    let mut req = RequestHeader::build("GET",b"/",Some(names.len()+4))?;
+    for name in names.iter(){
+        for v in origin.headers.get_all(name.as_str()).iter(){
+            req.append_header(name.clone(),v)?;
+        }
+    }
+    let uri = origin.uri.path_and_query().map(|x|x.as_str()).unwrap_or("/").to_string();
+    req.insert_header("X-Original-URI",uri)?;
+    req.insert_header("X-Original-Method",origin.method.as_str().to_string())?;
+    if let Some(host) = origin.headers.get("Host"){
+        req.insert_header("X-Forwarded-Host",host)?;
+    }
+    if let Some(ip) = ctx.client_ip{
+        req.insert_header("X-Real-IP",ip.to_string())?;
+    }
+    Ok(req)
+}
+
+fn copy_headers(resp:&ResponseHeader,names:&[String])->Vec<(String,Bytes)>{
+    let mut list = vec![];
+    for name in names.iter(){
+        for v in resp.headers.get_all(name.as_str()).iter(){
+            list.push((name.clone(),Bytes::copy_from_slice(v.as_bytes())));
+        }
+    }
+    list
+}
+
+//the configured auth response headers are always overwritten, so the client can not fake them
+pub fn set_auth_headers(req:&mut RequestHeader,ctx:&mut HttpProxyCtx)->Result<()>{
+    let auth = if let Some(ext) = ctx.service.as_ref().and_then(|x|x.policy.ext_auth.as_ref()){ ext }else{ return Ok(()) };
+    for name in auth.response_headers.iter(){
+        req.remove_header(name.as_str());
+    }
+    for (k,v) in std::mem::take(&mut ctx.auth_headers){
+        req.append_header(k,v.as_ref())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use pingora::http::{RequestHeader, ResponseHeader};
+    use crate::pkg::annotation::IngPolicy;
+    use crate::pkg::ingress::IngRule;
+    use crate::service::http_proxy::test::{request, start_proxy};
+    use crate::service::ext_auth::{auth_request, copy_headers};
+    use crate::service::http_proxy::HttpProxyCtx;
+
+    #[test]
+    fn test_auth_request(){
+        let mut origin = RequestHeader::build("POST",b"/api/user?id=1",None).unwrap();
+        origin.insert_header("Host","test.com").unwrap();
+        origin.insert_header("Authorization","Bearer xxx").unwrap();
+        origin.insert_header("Cookie","a=1").unwrap();
+        let ctx = HttpProxyCtx{client_ip:Some("1.2.3.4".parse().unwrap()),..Default::default()};
+        let req = auth_request(&origin,&["Authorization".to_string()],&ctx).unwrap();
+        assert_eq!(req.method.as_str(),"GET");
+        assert_eq!(req.headers.get("Authorization").unwrap(),"Bearer xxx");
+        assert!(req.headers.get("Cookie").is_none());
+        assert_eq!(req.headers.get("X-Original-URI").unwrap(),"/api/user?id=1");
+        assert_eq!(req.headers.get("X-Original-Method").unwrap(),"POST");
+        assert_eq!(req.headers.get("X-Forwarded-Host").unwrap(),"test.com");
+        assert_eq!(req.headers.get("X-Real-IP").unwrap(),"1.2.3.4");
+
+        let mut resp = ResponseHeader::build(200,None).unwrap();
+        resp.insert_header("X-User","bob").unwrap();
+        resp.insert_header("X-Other","1").unwrap();
+        let list = copy_headers(&resp,&["X-User".to_string(),"X-Group".to_string()]);
+        assert_eq!(list.len(),1);
+        assert_eq!(list[0].0,"X-User");
+        assert_eq!(list[0].1.as_ref(),b"bob");
+    }
+
+    //the invalid auth url denies the requests instead of leaving the route open
+    #[tokio::test]
+    async fn test_invalid_ext_auth_denied(){
+        for (i,url) in ["ftp://auth.test.com/check","not a url"].iter().enumerate(){
+            let an = BTreeMap::from([("pingora.ingress/auth-url".to_string(),url.to_string())]);
+            let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+            assert!(!errors.is_empty());
+            assert!(policy.ext_auth.as_ref().unwrap().url.is_none());
+            let host = format!("ext{}.test.com",i);
+            let rule = IngRule{path:"/".into(),ty:1,backend:"127.0.0.1".into(),port:1,..Default::default()}.set_policy(policy);
+            let addr = start_proxy(Default::default(),host.as_str(),vec![rule]).await;
+            let resp = request(addr,format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n",host).as_str()).await;
+            assert!(resp.starts_with("http/1.1 500"),"{}",resp);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::net::IpAddr;
+use pingora::http::RequestHeader;
+use pingora::prelude::*;
+use crate::infra::ip::CidrSet;
+use crate::service::http_proxy::HttpProxyCtx;
+
+//set X-Forwarded-*, X-Real-IP and Forwarded to the upstream request.
+//the values from a trusted proxy are appended or kept, otherwise they are overwritten.
+pub fn set_forwarded_headers(session:&Session,req:&mut RequestHeader,ctx:&HttpProxyCtx,trusted:&CidrSet,rfc7239:bool)->Result<()>{
+    let peer = if let Some(ip) = ctx.peer{ ip }else{ return Ok(()) };
+    let from_trusted = trusted.contains(&peer);
+    let incoming = |name:&str|{
+        let list = req.headers.get_all(name).iter().filter_map(|x|x.to_str().ok()).collect::<Vec<_>>();
+        if from_trusted && !list.is_empty() {Some(list.join(", "))}else{None}
+    };
+    let proto = if downstream_is_tls(session) {"https"}else{"http"};
+    let host = req.headers.get("Host").and_then(|x|x.to_str().ok()).unwrap_or_default().to_string();
+
+    let xff = append_value(incoming("X-Forwarded-For"),peer.to_string());
+    let xfp = incoming("X-Forwarded-Proto").unwrap_or(proto.to_string());
+    let xfh = incoming("X-Forwarded-Host").unwrap_or(host.clone());
+    let forwarded = append_value(incoming("Forwarded"),forwarded_element(peer,proto,host.as_str()));
+
+    req.insert_header("X-Forwarded-For",xff)?;
+    req.insert_header("X-Forwarded-Proto",xfp)?;
+    if !xfh.is_empty() {
+        req.insert_header("X-Forwarded-Host",xfh)?;
+    }
+    if let Some(ip) = ctx.client_ip{
+        req.insert_header("X-Real-IP",ip.to_string())?;
+    }
+    if rfc7239 {
+        req.insert_header("Forwarded",forwarded)?;
+    }
+    Ok(())
+}
+
+//the client uses https, the X-Forwarded-Proto of a trusted proxy is used, e.g. the tls terminated by a load balancer
+pub fn request_is_https(session:&Session,ctx:&HttpProxyCtx,trusted:&CidrSet)->bool{
+    if downstream_is_tls(session) {
+        return true
+    }
+    let from_trusted = ctx.peer.map(|x|trusted.contains(&x)).unwrap_or(false);
+    if !from_trusted {
+        return false
+    }
+    //the last value is set by the nearest proxy
+    session.req_header().headers.get_all("X-Forwarded-Proto").iter()
+        .filter_map(|x|x.to_str().ok())
+        .flat_map(|x|x.split(','))
+        .last()
+        .map(|x|x.trim().eq_ignore_ascii_case("https"))
+        .unwrap_or(false)
+}
+
+pub fn downstream_is_tls(session:&Session)->bool{
+    let digest = if let Some(s) = session.as_http1(){
+        Some(s.digest())
+    }else{
+        session.as_http2().and_then(|s|s.digest())
+    };
+    digest.map(|x|x.ssl_digest.is_some()).unwrap_or(false)
+}
+
+fn append_value(incoming:Option<String>,value:String)->String{
+    match incoming {
+        Some(s) => format!("{}, {}",s,value),
+        None => value,
+    }
+}
+
+//RFC 7239: ipv6 must be quoted and bracketed, host with port must be quoted
+fn forwarded_element(peer:IpAddr,proto:&str,host:&str)->String{
+    let peer = match peer {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"",ip),
+    };
+    let mut element = format!("for={};proto={}",peer,proto);
+    if !host.is_empty() {
+        if host.chars().all(|c|c.is_ascii_alphanumeric() || "-._".contains(c)) {
+            element.push_str(format!(";host={}",host).as_str());
+        }else{
+            element.push_str(format!(";host=\"{}\"",host.replace('\\',"\\\\").replace('"',"\\\"")).as_str());
+        }
+    }
+    element
+}
+
+#[cfg(test)]
+mod test{
+    use crate::service::forwarded::{append_value, forwarded_element};
+
+    #[test]
+    fn test_forwarded_value(){
+        assert_eq!(forwarded_element("1.2.3.4".parse().unwrap(),"http","test.com"),"for=1.2.3.4;proto=http;host=test.com");
+        assert_eq!(forwarded_element("2001:db8::1".parse().unwrap(),"https","test.com:8080"),"for=\"[2001:db8::1]\";proto=https;host=\"test.com:8080\"");
+        assert_eq!(append_value(Some("1.1.1.1".into()),"2.2.2.2".into()),"1.1.1.1, 2.2.2.2");
+        assert_eq!(append_value(None,"2.2.2.2".into()),"2.2.2.2");
+    }
+}
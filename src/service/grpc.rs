@@ -0,0 +1,106 @@
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use pingora::protocols::http::ServerSession as HttpSession;
+use pingora::protocols::http::v2::server::HttpSession as H2Session;
+
+pub fn is_grpc_request(session:&Session)->bool{
+    session.req_header().headers.get("Content-Type")
+        .and_then(|x|x.to_str().ok())
+        .map(|x|x.starts_with("application/grpc"))
+        .unwrap_or(false)
+}
+
+//https://github.com/grpc/grpc/blob/master/doc/http-grpc-status-mapping.md
+fn grpc_status(code:u16)->(u16,&'static str){
+    match code {
+        400 => (13,"INTERNAL"),
+        401 => (16,"UNAUTHENTICATED"),
+        403 => (7,"PERMISSION_DENIED"),
+        404 => (12,"UNIMPLEMENTED"),
+        429 | 502 | 503 | 504 => (14,"UNAVAILABLE"),
+        _ => (2,"UNKNOWN"),
+    }
+}
+
+//a trailers-only grpc response, the grpc status is carried by the headers
+pub fn grpc_error_header(code:u16)->Result<ResponseHeader>{
+    let (status,name) = grpc_status(code);
+    let mut resp = ResponseHeader::build(200,Some(3))?;
+    resp.insert_header("Content-Type","application/grpc")?;
+    resp.insert_header("grpc-status",status.to_string())?;
+    resp.insert_header("grpc-message",format!("gateway error: http {} {}",code,name).replace(' ',"%20"))?;
+    Ok(resp)
+}
+
+//the headers of http/2 end the stream, an empty data frame after them is not trailers-only
+fn write_h2_grpc_error(s:&mut H2Session,resp:ResponseHeader)->Result<()>{
+    s.write_response_header(Box::new(resp),true)
+}
+
+pub async fn write_grpc_error(session:&mut Session,code:u16)->Result<()>{
+    let resp = grpc_error_header(code)?;
+    if let HttpSession::H2(s) = session.as_mut() {
+        return write_h2_grpc_error(s,resp)
+    }
+    session.as_mut().set_keepalive(None);
+    session.write_response_header(Box::new(resp)).await?;
+    session.as_mut().finish_body().await
+}
+
+//the same as the default status of pingora
+pub fn error_code(e:&Error)->u16{
+    match e.etype() {
+        ErrorType::HTTPStatus(code) => *code,
+        _ => match e.esource() {
+            ErrorSource::Upstream => 502,
+            ErrorSource::Downstream => match e.etype() {
+                ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed => 0,
+                _ => 400,
+            },
+            ErrorSource::Internal | ErrorSource::Unset => 500,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::sync::Arc;
+    use pingora::protocols::http::v2::server::{handshake, HttpSession};
+    use pingora::protocols::l4::stream::Stream;
+    use crate::service::grpc::{grpc_error_header, write_h2_grpc_error};
+
+    #[test]
+    fn test_grpc_error_header(){
+        let resp = grpc_error_header(503).unwrap();
+        assert_eq!(resp.status.as_u16(),200);
+        assert_eq!(resp.headers.get("grpc-status").unwrap(),"14");
+        assert_eq!(resp.headers.get("grpc-message").unwrap(),"gateway%20error:%20http%20503%20UNAVAILABLE");
+        let resp = grpc_error_header(401).unwrap();
+        assert_eq!(resp.headers.get("grpc-status").unwrap(),"16");
+    }
+
+    #[tokio::test]
+    async fn test_grpc_error_trailers_only(){
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move{
+            let (tcp,_) = listener.accept().await.unwrap();
+            let mut conn = handshake(Box::new(Stream::from(tcp)),None).await.unwrap();
+            let mut session = HttpSession::from_h2_conn(&mut conn,Arc::default()).await.unwrap().unwrap();
+            write_h2_grpc_error(&mut session,grpc_error_header(404).unwrap()).unwrap();
+            //the connection is driven by accept
+            while let Ok(Some(_)) = HttpSession::from_h2_conn(&mut conn,Arc::default()).await {}
+        });
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (client,conn) = h2::client::handshake(tcp).await.unwrap();
+        tokio::spawn(conn);
+        let mut client = client.ready().await.unwrap();
+        let req = http::Request::post(format!("http://{}/svc/Method",addr)).header("content-type","application/grpc").body(()).unwrap();
+        let (resp,_) = client.send_request(req,true).unwrap();
+        let resp = resp.await.unwrap();
+        assert_eq!(resp.headers().get("grpc-status").unwrap(),"12");
+        //the headers frame carries END_STREAM
+        assert!(resp.body().is_end_stream());
+    }
+}
@@ -0,0 +1,120 @@
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::prelude::*;
+use crate::pkg::annotation::IngHeaderRules;
+use crate::service::http_proxy::HttpProxyCtx;
+
+const REQUEST_ID_HEADER:&str = "X-Request-ID";
+
+//use the request id from the client, or generate a new one
+pub fn request_id(session:&Session)->String{
+    if let Some(s) = session.req_header().headers.get(REQUEST_ID_HEADER).and_then(|x|x.to_str().ok()){
+        if !s.is_empty() && s.len() <= 128 {
+            return s.to_string()
+        }
+    }
+    format!("{:032x}",rand::random::<u128>())
+}
+
+struct HeaderVars<'a>{
+    client_ip:String,
+    host:&'a str,
+    request_id:&'a str,
+    path:&'a str,
+}
+
+impl<'a> HeaderVars<'a>{
+    fn new(session:&'a Session,ctx:&'a HttpProxyCtx)->Self{
+        let host = session.req_header().headers.get("Host").and_then(|x|x.to_str().ok()).unwrap_or_default();
+        Self{
+            client_ip: ctx.client_ip.map(|x|x.to_string()).unwrap_or_default(),
+            host,
+            request_id: ctx.request_id.as_str(),
+            path: ctx.service.as_ref().map(|x|x.path.as_str()).unwrap_or_default(),
+        }
+    }
+    fn interpolate(&self,value:&str)->String{
+        if !value.contains("${") {
+            return value.to_string()
+        }
+        value.replace("${client_ip}",self.client_ip.as_str())
+            .replace("${host}",self.host)
+            .replace("${request_id}",self.request_id)
+            .replace("${path}",self.path)
+    }
+}
+
+//remove first, then set and add. request and response header have no common trait
+macro_rules! apply_header_rules {
+    ($header:expr,$rules:expr,$vars:expr) => {
+        for name in $rules.remove.iter(){
+            $header.remove_header(name.as_str());
+        }
+        for (k,v) in $rules.set.iter(){
+            $header.insert_header(k.clone(),$vars.interpolate(v))?;
+        }
+        for (k,v) in $rules.add.iter(){
+            $header.append_header(k.clone(),$vars.interpolate(v))?;
+        }
+    };
+}
+
+//the default headers of config are applied before the rules of route
+pub fn set_request_headers(session:&Session,req:&mut RequestHeader,ctx:&HttpProxyCtx,defaults:&IngHeaderRules)->Result<()>{
+    let rules = ctx.service.as_ref().map(|x|&x.policy.headers.request).filter(|x|!x.is_empty());
+    if rules.is_none() && defaults.is_empty() {
+        return Ok(())
+    }
+    let vars = HeaderVars::new(session,ctx);
+    apply_header_rules!(req,defaults,vars);
+    if let Some(rules) = rules {
+        apply_header_rules!(req,rules,vars);
+    }
+    Ok(())
+}
+
+pub fn set_response_headers(session:&Session,resp:&mut ResponseHeader,ctx:&HttpProxyCtx,defaults:&IngHeaderRules)->Result<()>{
+    let rules = ctx.service.as_ref().map(|x|&x.policy.headers.response).filter(|x|!x.is_empty());
+    if rules.is_none() && defaults.is_empty() {
+        return Ok(())
+    }
+    let vars = HeaderVars::new(session,ctx);
+    apply_header_rules!(resp,defaults,vars);
+    if let Some(rules) = rules {
+        apply_header_rules!(resp,rules,vars);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use pingora::http::RequestHeader;
+    use crate::pkg::annotation::IngPolicy;
+    use crate::service::headers::HeaderVars;
+
+    #[test]
+    fn test_header_rules(){
+        let mut an = BTreeMap::new();
+        an.insert("pingora.ingress/request-header-set".to_string(),"X-Client: ${client_ip}\nX-Route: ${host}${path}\nX-Bad: ${unknown}".to_string());
+        an.insert("pingora.ingress/request-header-add".to_string(),"X-Id: ${request_id}".to_string());
+        an.insert("pingora.ingress/request-header-remove".to_string(),"X-Secret".to_string());
+        let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+        assert_eq!(errors.len(),1);
+        let rules = policy.headers.request;
+        assert_eq!(rules.set.len(),2);
+
+        let vars = HeaderVars{client_ip:"1.2.3.4".into(),host:"test.com",request_id:"abc",path:"/api"};
+        let mut req = RequestHeader::build("GET",b"/api/user",None).unwrap();
+        req.insert_header("X-Secret","1").unwrap();
+        req.insert_header("X-Id","0").unwrap();
+        let f = |req:&mut RequestHeader|->pingora::Result<()>{
+            apply_header_rules!(req,rules,vars);
+            Ok(())
+        };
+        f(&mut req).unwrap();
+        assert!(req.headers.get("X-Secret").is_none());
+        assert_eq!(req.headers.get("X-Client").unwrap(),"1.2.3.4");
+        assert_eq!(req.headers.get("X-Route").unwrap(),"test.com/api");
+        assert_eq!(req.headers.get_all("X-Id").iter().count(),2);
+    }
+}
@@ -1,18 +1,55 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, TcpStream};
+use std::os::fd::RawFd;
 use std::sync::Arc;
+use std::time::Duration;
 use async_channel::Receiver;
 use wd_tools::sync::Acl;
+use crate::infra::ip::{CidrSet, real_client_ip};
 use crate::infra::url_tree::Node;
-use crate::pkg::ingress::{IngressEvent, IngRule};
+use crate::pkg::annotation::IngPolicy;
+use crate::pkg::ingress::{IngBackendRef, IngConditions, IngressEvent, IngRule};
+use crate::service::config::Config;
+use crate::pkg::secret::SecretStore;
+use crate::service::access::access_filter;
+use crate::service::auth::BasicAuthFilter;
+use crate::service::canary::{CanaryNode, remove_canary, select_canary};
+use crate::service::cors::{cors_preflight, set_cors_headers};
+use crate::service::ext_auth::{ExtAuthFilter, set_auth_headers};
+use crate::service::jwt::{JwtFilter, set_claim_headers};
+use crate::service::forwarded::{downstream_is_tls, set_forwarded_headers};
+use crate::service::grpc::{error_code, is_grpc_request, write_grpc_error};
+use crate::service::headers::{request_id, set_request_headers, set_response_headers};
+use crate::service::limit::RateLimitFilter;
+use crate::service::mirror::MirrorFilter;
+use crate::service::redirect::redirect_filter;
+use crate::service::rewrite::set_rewrite_path;
+use crate::service::route_match::{merge_node, RouteMatcher, select_node};
+use crate::service::passthrough::{PassthroughClients, PassthroughProxy};
+use crate::service::tls::{CertResolver, host_routers};
+use crate::service::websocket::{set_websocket_peer_options, upstream_conn, WebsocketSession};
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::listeners::TlsSettings;
+use pingora::protocols::Digest;
 use pingora::prelude::*;
-use wd_tools::PFArc;
+use pingora::upstreams::peer::PeerOptions;
+use rand::Rng;
+use regex::Regex;
 
-#[derive(Default)]
 pub struct HttpProxyControl{
     router : Acl<HashMap<String,Router>>,
+    cfg : Acl<Config>,
+    trusted_proxies : CidrSet,
+    limit : RateLimitFilter,
+    basic_auth : BasicAuthFilter,
+    ext_auth : ExtAuthFilter,
+    mirror : MirrorFilter,
+    jwt : JwtFilter,
+    secrets : SecretStore,
+    passthrough_clients : PassthroughClients,
 }
 impl HttpProxyControl {
-    pub async fn new_ing_event_watch(recv:Receiver<IngressEvent>)->Self{
+    pub async fn new_ing_event_watch(recv:Receiver<IngressEvent>,cfg:Acl<Config>,secrets:SecretStore)->Self{
         let router = Acl::default();
         let rt = router.clone();
         tokio::spawn(async move{
@@ -22,7 +59,45 @@ impl HttpProxyControl {
             }
             wd_log::log_info_ln!("IngressEvent receiver channel over");
         });
-        Self{router}
+        let start = cfg.share();
+        let (trusted_proxies,invalid) = CidrSet::parse(&start.trusted_proxies);
+        if !invalid.is_empty() {
+            wd_log::log_warn_ln!("invalid trusted proxies:{:?}",invalid);
+        }
+        if start.passthrough_port > 0 && trusted_proxies.contains(&IpAddr::from([127,0,0,1])) {
+            wd_log::log_warn_ln!("trusted proxies contain 127.0.0.1, the local clients can set the client ip by {}",start.real_ip_header);
+        }
+        let limit = RateLimitFilter::default();
+        let basic_auth = BasicAuthFilter::new(secrets.clone());
+        let ext_auth = ExtAuthFilter::new(Duration::from_secs(start.auth_timeout.max(1)));
+        let mirror = MirrorFilter::new(Duration::from_secs(start.mirror_timeout.max(1)),start.mirror_max_concurrency as usize);
+        let jwt = JwtFilter::new(secrets.clone(),Duration::from_secs(start.auth_timeout.max(1)),Duration::from_secs(start.jwks_cache_ttl));
+        Self{router,cfg,trusted_proxies,limit,basic_auth,ext_auth,mirror,jwt,secrets,passthrough_clients:PassthroughClients::default()}
+    }
+    //the https listener resolves the certificate by sni from the routers
+    pub fn tls_settings(&self)->Result<TlsSettings>{
+        let mut settings = TlsSettings::with_callbacks(Box::new(CertResolver::new(self.router.clone(),self.secrets.clone(),self.cfg.clone())))?;
+        if self.cfg.share().http2 {
+            settings.enable_h2();
+        }
+        Ok(settings)
+    }
+    pub fn passthrough_proxy(&self)->PassthroughProxy{
+        PassthroughProxy::new(self.router.clone(),self.cfg.clone(),self.passthrough_clients.clone())
+    }
+    //the peer of the connection, the connection forwarded by the passthrough listener is from its client
+    fn peer_ip(&self,session:&Session)->Option<IpAddr>{
+        let peer = session.client_addr().and_then(|x|x.as_inet())?;
+        Some(self.passthrough_clients.resolve(*peer).ip())
+    }
+    fn client_ip(&self,session:&Session,peer:Option<IpAddr>)->Option<IpAddr>{
+        let peer = peer?;
+        let forwarded = session.req_header().headers.get_all(self.cfg.share().real_ip_header.as_str()).iter()
+            .filter_map(|x|x.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        let forwarded = if forwarded.is_empty() {None}else{Some(forwarded.as_str())};
+        Some(real_client_ip(peer,forwarded,&self.trusted_proxies))
     }
     fn ing_event_to_router(ing:IngressEvent,acl:Acl<HashMap<String,Router>>){
         let IngressEvent{
@@ -49,10 +124,38 @@ impl HttpProxyControl {
                     map.remove("*");
                 }
                 for i in hosts{
+                    //a canary ingress only takes the canary off the primary
+                    let canary = !i.rules.is_empty() && i.rules.iter().all(|x|x.policy.canary.is_some());
+                    if canary {
+                        if let Some(router) = map.get_mut(i.host.as_str()) {
+                            if i.rules.iter().all(|x|router.remove_canary(x)) {
+                                wd_log::log_debug_ln!("delete canary of host:[{}]",i.host.as_str());
+                                continue
+                            }
+                        }
+                    }
                     wd_log::log_debug_ln!("delete host:[{}]",i.host.as_str());
                     map.remove(i.host.as_str());
                 }
             }
+            4=>{ //replace the rules of the hosts, a host without rules is removed. the passthrough backend is from the rules
+                for i in hosts{
+                    let old = map.remove(i.host.as_str());
+                    if i.rules.is_empty() && old.as_ref().map(|x|x.default_backend.is_none()).unwrap_or(true) {
+                        wd_log::log_debug_ln!("delete host:[{}]",i.host.as_str());
+                        continue
+                    }
+                    wd_log::log_debug_ln!("replace host:[{}]",i.host.as_str());
+                    let mut router = Router::from_host(i.host.clone());
+                    if let Some(old) = old {
+                        router.sni = old.sni;
+                        router.tls_secret = old.tls_secret;
+                        router.default_backend = old.default_backend;
+                    }
+                    router.update_from_ing_rule(i.rules);
+                    map.insert(i.host,router);
+                }
+            }
             _=>{
                 wd_log::log_info_ln!("unknown ingress event type:{:?}",ty);
                 return;
@@ -63,6 +166,11 @@ impl HttpProxyControl {
                 router.sni = i;
             }
         }
+        for (host,i) in sni.certs{
+            if let Some(router) = map.get_mut(host.as_str()){
+                router.tls_secret = i;
+            }
+        }
         acl.update(move |_|{
             map
         });
@@ -72,30 +180,86 @@ impl HttpProxyControl {
 #[derive(Default,Clone,Debug)]
 pub struct Router{
     pub sni:String,
+    //namespace/name of the tls secret, used by the https listener
+    pub tls_secret:String,
+    //backend:port of the ssl passthrough, the tls stream is not terminated
+    pub passthrough:Option<String>,
+    //owner ingress => passthrough backend
+    passthroughs:BTreeMap<String,String>,
     pub host:String,
     pub default_backend:Option<Arc<RouterNode>>,
     pub exact:HashMap<String,Arc<RouterNode>>,
+    //ImplementationSpecific path, match in order
+    pub regex:Vec<Arc<RouterNode>>,
     pub prefix:Node<RouterNode>,
 }
 #[derive(Clone,Debug)]
 pub struct RouterNode{
+    pub host:String,
+    pub path:String,
     pub backend: String,
     pub port:i32,
+    pub policy:IngPolicy,
+    pub regex:Option<Regex>,
+    pub conditions:IngConditions,
+    pub matcher:RouteMatcher,
+    pub backends:Vec<IngBackendRef>,
+    //the nodes of the same path with conditions, tried before this one
+    pub alternatives:Vec<Arc<RouterNode>>,
+    //the canary ingress of the same host and path
+    pub canary:Option<Arc<CanaryNode>>,
+    //namespace/name of the ingress
+    pub owner:String,
+    pub tls_only:bool,
 }
 impl From<IngRule> for RouterNode{
     fn from(value: IngRule) -> Self {
-        let backend = value.backend;
-        let port = value.port;
-        Self{backend,port}
+        let IngRule{ path, ty, backend, port, policy, conditions, backends, owner, tls_only } = value;
+        //the regex has been checked when the rule is created
+        let regex = if ty == 3 {
+            Regex::new(format!("^(?:{})",path).as_str()).ok()
+        }else{None};
+        let matcher = RouteMatcher::from(&conditions);
+        Self{host:String::new(),path,backend,port,policy,regex,conditions,matcher,backends,alternatives:vec![],canary:None,owner,tls_only}
     }
 }
 
 impl RouterNode {
-    pub fn new<B:Into<String>>(backend:B,port:i32)->Self{
-        let backend = backend.into();
-        Self{backend,port}
+    pub fn set_host<S:Into<String>>(mut self,host:S)->Self{
+        self.host = host.into();self
+    }
+    //a weighted random backend, none means no backend available
+    pub fn pick_backend(&self)->Option<(&str,i32)>{
+        if self.backends.is_empty() {
+            return if self.backend.is_empty() {None}else{Some((self.backend.as_str(),self.port))}
+        }
+        let total = self.backends.iter().map(|x|x.weight).sum::<u32>();
+        if total == 0 {
+            return None
+        }
+        let mut n = rand::thread_rng().gen_range(0..total);
+        for i in self.backends.iter(){
+            if n < i.weight {
+                return Some((i.backend.as_str(),i.port))
+            }
+            n -= i.weight;
+        }
+        None
+    }
+    //ingress annotation first, then the gateway default. zero means not limit.
+    pub fn set_peer_options(&self,cfg:&Config,opt:&mut PeerOptions){
+        let timeout = |val:Option<u64>,df:u64|{
+            match val.unwrap_or(df) {
+                0 => None,
+                n => Some(Duration::from_secs(n)),
+            }
+        };
+        let ing = &self.policy.timeout;
+        opt.connection_timeout = timeout(ing.connect,cfg.proxy_connect_timeout);
+        opt.read_timeout = timeout(ing.read,cfg.proxy_read_timeout);
+        opt.write_timeout = timeout(ing.send,cfg.proxy_send_timeout);
+        opt.idle_timeout = timeout(ing.idle,cfg.proxy_idle_timeout);
     }
-
 }
 impl Router{
     pub fn from_host<S:Into<String>>(host:S)->Self{
@@ -103,21 +267,89 @@ impl Router{
         Self{host,..Default::default()}
     }
     pub fn from_default_backend(ir:IngRule)->Self{
-        let mut rt = Router::default();
-        rt.default_backend = Some(Arc::new(RouterNode::from(ir)));
-        rt
+        Router{
+            default_backend: Some(Arc::new(RouterNode::from(ir).set_host("*"))),
+            ..Default::default()
+        }
+    }
+    //exact, regex, then the longest prefix. the conditions of the node are checked at last
+    pub fn find(&self,req:&RequestHeader)->Option<Arc<RouterNode>>{
+        let path = req.uri.path();
+        let node = if let Some(s) = self.exact.get(path) {
+            s.clone()
+        }else if let Some(s) = self.regex.iter().find(|x|x.regex.as_ref().map(|x|x.is_match(path)).unwrap_or(false)){
+            s.clone()
+        }else{
+            self.prefix.find_by_path(path)?
+        };
+        select_node(&node,req)
+    }
+    //false when the path has no primary node with the canary
+    pub fn remove_canary(&mut self,rule:&IngRule)->bool{
+        let path = rule.path.as_str();
+        match rule.ty {
+            1 => match self.prefix.get_path(path).and_then(|x|remove_canary(&x,&rule.conditions)) {
+                Some(n) => {self.prefix.insert_path(path,n);true}
+                None => false,
+            },
+            2 => match self.exact.get(path).and_then(|x|remove_canary(x,&rule.conditions)) {
+                Some(n) => {self.exact.insert(path.to_string(),n);true}
+                None => false,
+            },
+            3 => match self.regex.iter_mut().find(|x|x.path == path) {
+                Some(old) => match remove_canary(old,&rule.conditions) {
+                    Some(n) => {*old = n;true}
+                    None => false,
+                },
+                None => false,
+            },
+            _ => false,
+        }
+    }
+    //the passthrough of the ingresses in the rules is recomputed, an ingress without it is removed.
+    //a host has one passthrough backend, the root path is preferred
+    fn update_passthrough(&mut self,rules:&[IngRule]){
+        let mut owners = BTreeMap::new();
+        for r in rules.iter(){
+            let addr = owners.entry(r.owner.as_str()).or_insert(None);
+            if r.policy.backend.ssl_passthrough && (addr.is_none() || r.path == "/") {
+                *addr = Some(format!("{}:{}",r.backend,r.port));
+            }
+        }
+        for (owner,addr) in owners{
+            match addr {
+                Some(s) => self.passthroughs.insert(owner.to_string(),s),
+                None => self.passthroughs.remove(owner),
+            };
+        }
+        self.passthrough = self.passthroughs.values().next().cloned();
     }
     pub fn update_from_ing_rule(&mut self,rules:Vec<IngRule>){
+        self.update_passthrough(&rules);
         for rule in rules{
-            let IngRule{ path, backend, port,ty } = rule;
-            match ty {
+            let path = rule.path.clone();
+            match rule.ty {
                 1=>{ //prefix
-                    wd_log::log_debug_ln!("insert prefix rule: path[{}] service[{}] port[{}]",path,backend,port);
-                    self.prefix.insert_path(path.as_str(),RouterNode::new(backend,port).arc());
+                    wd_log::log_debug_ln!("insert prefix rule: path[{}] service[{}] port[{}]",path,rule.backend,rule.port);
+                    let node = merge_node(self.prefix.get_path(path.as_str()),RouterNode::from(rule).set_host(self.host.as_str()));
+                    self.prefix.insert_path(path.as_str(),node);
                 }
                 2=>{ //exact
-                    wd_log::log_debug_ln!("insert exact rule: path[{}] service[{}] port[{}]",path,backend,port);
-                    self.exact.insert(path,RouterNode::new(backend,port).arc());
+                    wd_log::log_debug_ln!("insert exact rule: path[{}] service[{}] port[{}]",path,rule.backend,rule.port);
+                    let node = merge_node(self.exact.remove(path.as_str()),RouterNode::from(rule).set_host(self.host.as_str()));
+                    self.exact.insert(path,node);
+                }
+                3=>{ //regex
+                    wd_log::log_debug_ln!("insert regex rule: path[{}] service[{}] port[{}]",path,rule.backend,rule.port);
+                    let node = RouterNode::from(rule).set_host(self.host.as_str());
+                    if let Some(old) = self.regex.iter_mut().find(|x|x.path == path){
+                        *old = merge_node(Some(old.clone()),node);
+                    }else{
+                        self.regex.push(merge_node(None,node));
+                    }
+                }
+                0=>{ //the passthrough backend only
+                    wd_log::log_debug_ln!("passthrough rule: service[{}] port[{}]",rule.backend,rule.port);
                 }
                 _=>{
                     wd_log::log_warn_ln!("RouterNode do not support specific path:{}",path);
@@ -129,8 +361,21 @@ impl Router{
 
 #[derive(Default)]
 pub struct HttpProxyCtx{
-    service:Option<Arc<RouterNode>>,
-    sni:String,
+    pub service:Option<Arc<RouterNode>>,
+    pub sni:String,
+    //the downstream peer, the trusted proxies are matched with it
+    pub peer:Option<IpAddr>,
+    pub client_ip:Option<IpAddr>,
+    pub request_id:String,
+    //the headers from external auth response
+    pub auth_headers:Vec<(String,bytes::Bytes)>,
+    //the headers from jwt claims
+    pub claim_headers:Vec<(String,String)>,
+    pub websocket:Option<WebsocketSession>,
+    //the upstream socket of an upgrade request with websocket-max-duration
+    pub websocket_conn:Option<TcpStream>,
+    //send a copy of the request to the mirror target when it is done
+    pub mirror:bool,
 }
 
 #[async_trait::async_trait]
@@ -144,9 +389,27 @@ impl ProxyHttp for HttpProxyControl{
 
 
 
-    async fn upstream_peer(&self, _session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
+    async fn upstream_peer(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
         let peer = if let Some(ref s) = ctx.service {
-            Box::new(HttpPeer::new((s.backend.as_str(),s.port as u16), !ctx.sni.is_empty(), ctx.sni.clone()))
+            let (backend,port) = if let Some(s) = s.pick_backend(){ s }else{
+                return Error::err(ErrorType::HTTPStatus(500));
+            };
+            let mut peer = if let Some(protocol) = s.policy.backend.protocol{
+                let mut peer = HttpPeer::new((backend,port as u16), protocol.is_tls(), backend.to_string());
+                let (max,min) = protocol.http_version();
+                peer.options.set_http_version(max,min);
+                peer.options.verify_cert = s.policy.backend.ssl_verify;
+                peer.options.verify_hostname = s.policy.backend.ssl_verify;
+                peer
+            }else{
+                HttpPeer::new((backend,port as u16), !ctx.sni.is_empty(), ctx.sni.clone())
+            };
+            let cfg = self.cfg.share();
+            s.set_peer_options(&cfg,&mut peer.options);
+            if session.is_upgrade_req() {
+                set_websocket_peer_options(s,&cfg,&mut peer.options);
+            }
+            Box::new(peer)
         }else{
             return Error::err(ErrorType::HTTPStatus(404));
         };
@@ -174,35 +437,200 @@ impl ProxyHttp for HttpProxyControl{
         wd_log::log_debug_ln!("request host[{}] path[{}]",host,path);
 
         let routers = self.router.share();
-        for i in 0..100{
-            if i != 0 {
-                let list:Vec<&str> = host.splitn(1,'.').collect();
-                if list.len() == 1 || list.is_empty() {
-                    break
-                }
-                host = list[1];
-            }
-            if let Some(r) = routers.get(host) {
-                ctx.sni = r.sni.clone();
-                if let Some(s) = r.exact.get(path) {
-                    ctx.service = Some(s.clone());
-                }else if let Some(s) = r.prefix.find_by_path(path){
-                    ctx.service = Some(s);
-                }
-                break
-            }
+        //the host is matched exactly or by a wildcard host, the others fall through to the default backend
+        if let Some(r) = host_routers(&routers,host).next() {
+            wd_log::log_debug_ln!("request match router host[{}]",r.host);
+            ctx.sni = r.sni.clone();
+            ctx.service = r.find(session.req_header());
         }
         //尝试兜底
         if ctx.service.is_none() {
             if let Some(r) = routers.get("*") {
                 ctx.sni = r.sni.clone();
-                ctx.service = r.default_backend.clone();
+                ctx.service = r.find(session.req_header()).or_else(||r.default_backend.clone());
             }
         }
+        ctx.service = ctx.service.take().map(|x|select_canary(x,session.req_header()));
+        //the route of the https listeners only is not served in cleartext
+        if ctx.service.as_ref().map(|x|x.tls_only).unwrap_or(false) && !downstream_is_tls(session) {
+            ctx.service = None;
+        }
         //如果没找到
         if ctx.service.is_none(){
             return Error::err(ErrorType::HTTPStatus(404));
         }
+        ctx.peer = self.peer_ip(session);
+        ctx.client_ip = self.client_ip(session,ctx.peer);
+        ctx.request_id = request_id(session);
+
+        if redirect_filter(session,ctx,&self.trusted_proxies,self.cfg.share().ssl_redirect_port).await? {
+            return Ok(true)
+        }
+        if access_filter(session,ctx).await? {
+            return Ok(true)
+        }
+        if self.limit.filter(session,ctx).await? {
+            return Ok(true)
+        }
+        //the preflight request has no credentials
+        if cors_preflight(session,ctx).await? {
+            return Ok(true)
+        }
+        if self.basic_auth.filter(session,ctx).await? {
+            return Ok(true)
+        }
+        if self.jwt.filter(session,ctx).await? {
+            return Ok(true)
+        }
+        if self.ext_auth.filter(session,ctx).await? {
+            return Ok(true)
+        }
+        ctx.mirror = MirrorFilter::sample(ctx);
         Ok(false)
     }
-}
\ No newline at end of file
+
+    async fn upstream_request_filter(&self, session: &mut Session, upstream_request: &mut RequestHeader, ctx: &mut Self::CTX) -> Result<()> where Self::CTX: Send + Sync {
+        let cfg = self.cfg.share();
+        set_forwarded_headers(session,upstream_request,ctx,&self.trusted_proxies,cfg.forwarded_header)?;
+        set_auth_headers(upstream_request,ctx)?;
+        set_claim_headers(upstream_request,ctx)?;
+        set_request_headers(session,upstream_request,ctx,&cfg.default_headers.request)?;
+        set_rewrite_path(upstream_request,ctx)?;
+        Ok(())
+    }
+
+    async fn response_filter(&self, session: &mut Session, upstream_response: &mut ResponseHeader, ctx: &mut Self::CTX) -> Result<()> where Self::CTX: Send + Sync {
+        if upstream_response.status.as_u16() == 101 {
+            if let Some(ref s) = ctx.service{
+                ctx.websocket = Some(WebsocketSession::new(s,ctx.websocket_conn.take()));
+            }
+        }
+        set_cors_headers(session,upstream_response,ctx)?;
+        set_response_headers(session,upstream_response,ctx,&self.cfg.share().default_headers.response)?;
+        Ok(())
+    }
+
+    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, _ctx: &mut Self::CTX) -> u16 where Self::CTX: Send + Sync {
+        let code = error_code(e);
+        if code == 0 {
+            return code
+        }
+        if is_grpc_request(session) {
+            if let Err(e) = write_grpc_error(session,code).await {
+                wd_log::log_error_ln!("failed to send grpc error response:{}",e);
+            }
+        }else{
+            session.as_mut().respond_error(code).await;
+        }
+        code
+    }
+
+    async fn connected_to_upstream(&self, session: &mut Session, _reused: bool, _peer: &HttpPeer, fd: RawFd, _digest: Option<&Digest>, ctx: &mut Self::CTX) -> Result<()> where Self::CTX: Send + Sync {
+        let max = ctx.service.as_ref().and_then(|x|x.policy.websocket.max_duration).unwrap_or(0);
+        if max > 0 && session.is_upgrade_req() {
+            ctx.websocket_conn = upstream_conn(fd);
+        }
+        Ok(())
+    }
+
+    async fn logging(&self, session: &mut Session, _e: Option<&Error>, ctx: &mut Self::CTX) where Self::CTX: Send + Sync {
+        self.mirror.mirror(session,ctx);
+    }
+
+    fn response_body_filter(&self, _session: &mut Session, _body: &mut Option<bytes::Bytes>, _end_of_stream: bool, ctx: &mut Self::CTX) -> Result<Option<Duration>> where Self::CTX: Send + Sync {
+        if let Some(ref ws) = ctx.websocket{
+            ws.check_duration()?;
+        }
+        Ok(None)
+    }
+}
+#[cfg(test)]
+pub mod test{
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use pingora::server::configuration::ServerConf;
+    use pingora::services::Service;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+    use wd_tools::sync::Acl;
+    use crate::pkg::ingress::{IngHost, IngressEvent, IngRule};
+    use crate::pkg::secret::SecretStore;
+    use crate::service::config::Config;
+    use crate::service::http_proxy::HttpProxyControl;
+
+    //start the http listener with the rules of the host, return the address when the rules are applied
+    pub async fn start_proxy(cfg:Config,host:&str,rules:Vec<IngRule>)->SocketAddr{
+        let hosts = vec![IngHost{host:host.into(),rules}];
+        start_proxy_with(cfg,IngressEvent{ty:1,default_backend:None,hosts,sni:Default::default(),ing:None,reports:vec![]}).await
+    }
+
+    pub async fn start_proxy_with(cfg:Config,event:IngressEvent)->SocketAddr{
+        let addr = free_addr();
+        serve(new_control(cfg,event).await,addr);
+        addr
+    }
+
+    //the control with the rules of the event applied
+    pub async fn new_control(cfg:Config,event:IngressEvent)->HttpProxyControl{
+        let (sender,recv) = async_channel::unbounded();
+        let hpc = HttpProxyControl::new_ing_event_watch(recv,Acl::new(cfg),SecretStore::default()).await;
+        let router = hpc.router.clone();
+        let hosts = event.hosts.iter().map(|x|x.host.clone()).collect::<Vec<_>>();
+        sender.send(event).await.unwrap();
+        while !hosts.iter().all(|x|router.share().contains_key(x.as_str())) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        hpc
+    }
+
+    pub fn free_addr()->SocketAddr{
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    //start the http listener of the control on addr
+    pub fn serve(hpc:HttpProxyControl,addr:SocketAddr){
+        let mut proxy = pingora::proxy::http_proxy_service(&Arc::new(ServerConf::default()),hpc);
+        proxy.add_tcp(addr.to_string().as_str());
+        tokio::spawn(async move{
+            let (_shutdown,watch) = tokio::sync::watch::channel(false);
+            proxy.start_service(None,watch).await
+        });
+    }
+
+    pub async fn connect(addr:SocketAddr)->TcpStream{
+        loop {
+            match TcpStream::connect(addr).await {
+                Ok(o) => return o,
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+    }
+
+    pub async fn read_head(stream:&mut TcpStream)->String{
+        let mut head = vec![];
+        let mut buf = [0u8;1];
+        while !head.ends_with(b"\r\n\r\n") {
+            assert_eq!(stream.read(&mut buf).await.unwrap(),1);
+            head.push(buf[0]);
+        }
+        String::from_utf8_lossy(&head).to_lowercase()
+    }
+
+    //send the request head, return the lowercase response head
+    pub async fn request(addr:SocketAddr,head:&str)->String{
+        let mut stream = connect(addr).await;
+        stream.write_all(head.as_bytes()).await.unwrap();
+        read_head(&mut stream).await
+    }
+
+    //the route of the https listeners only is not served on the http port
+    #[tokio::test]
+    async fn test_tls_only_route(){
+        let rule = IngRule{path:"/".into(),ty:1,backend:"127.0.0.1".into(),port:1,tls_only:true,..Default::default()};
+        let addr = start_proxy(Default::default(),"tls-only.test.com",vec![rule]).await;
+        let resp = request(addr,"GET / HTTP/1.1\r\nHost: tls-only.test.com\r\n\r\n").await;
+        assert!(resp.starts_with("http/1.1 404"),"{}",resp);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use pingora::http::RequestHeader;
+use pingora::prelude::*;
+use crate::infra::http_client::HttpClient;
+use crate::infra::jwt::{claim_to_string, Jwks, JwtError};
+use crate::pkg::annotation::JwksSource;
+use crate::pkg::secret::SecretStore;
+use crate::service::http_proxy::HttpProxyCtx;
+use crate::service::response::write_response;
+
+const JWKS_BODY_MAX:usize = 1024 * 1024;
+//a token with unknown kid triggers a refresh, but not more often than this
+const JWKS_REFRESH_MIN:Duration = Duration::from_secs(10);
+
+pub struct JwtFilter{
+    secrets:SecretStore,
+    client:HttpClient,
+    ttl:Duration,
+    //the jwks from url or file, key is the url or path
+    cache:Mutex<HashMap<String,(Instant,Arc<Jwks>)>>,
+}
+
+impl JwtFilter{
+    pub fn new(secrets:SecretStore,timeout:Duration,ttl:Duration)->Self{
+        Self{secrets,client:HttpClient::new(timeout),ttl,cache:Mutex::default()}
+    }
+    //return true if the token is invalid and 401 has been responded
+    pub async fn filter(&self,session:&mut Session,ctx:&mut HttpProxyCtx)->Result<bool>{
+        let node = if let Some(ref s) = ctx.service{ s.clone() }else{ return Ok(false) };
+        let jwt = if let Some(ref s) = node.policy.jwt{ s }else{ return Ok(false) };
+        if jwt.jwks == JwksSource::Invalid {
+            wd_log::log_warn_ln!("jwt of host[{}] path[{}] is invalid, deny all",node.host,node.path);
+            return write_response(session,500,vec![],None).await
+        }
+
+        let token = session.req_header().headers.get("Authorization")
+            .and_then(|x|x.to_str().ok())
+            .and_then(|x|{
+                let (scheme,token) = x.trim().split_once(' ')?;
+                if scheme.eq_ignore_ascii_case("bearer") {Some(token.trim().to_string())}else{None}
+            });
+        let token = if let Some(s) = token{ s }else{
+            return unauthorized(session,None,"bearer token is required").await
+        };
+
+        let mut result = match self.jwks(&jwt.jwks,false).await {
+            Some(jwks) => jwks.verify(token.as_str(),&jwt.issuer,&jwt.audience),
+            None => Err(JwtError::UnknownKey),
+        };
+        //the keys may be rotated
+        if result == Err(JwtError::UnknownKey) {
+            if let Some(jwks) = self.jwks(&jwt.jwks,true).await {
+                result = jwks.verify(token.as_str(),&jwt.issuer,&jwt.audience);
+            }
+        }
+        let claims = match result {
+            Ok(o) => o,
+            Err(e) => return unauthorized(session,Some("invalid_token"),e.to_string().as_str()).await,
+        };
+        for (claim,header) in jwt.claim_headers.iter(){
+            if let Some(value) = claims.get(claim).and_then(claim_to_string){
+                ctx.claim_headers.push((header.clone(),value));
+            }
+        }
+        Ok(false)
+    }
+    async fn jwks(&self,source:&JwksSource,refresh:bool)->Option<Arc<Jwks>>{
+        let key = match source {
+            JwksSource::Secret(s) => return self.secrets.get(s.as_str()).and_then(|x|x.jwks()),
+            JwksSource::Url(u) => u.to_string(),
+            JwksSource::File(f) => f.clone(),
+            JwksSource::Invalid => return None,
+        };
+        let cached = self.cache.lock().unwrap().get(key.as_str()).cloned();
+        if let Some((at,ref jwks)) = cached {
+            let expired = if refresh {at.elapsed() > JWKS_REFRESH_MIN}else{at.elapsed() > self.ttl};
+            if !expired {
+                return Some(jwks.clone())
+            }
+        }
+        let content = match source {
+            JwksSource::Url(u) => {
+                let req = RequestHeader::build("GET",b"/",None).ok()?;
+                self.client.send(u,req,None,JWKS_BODY_MAX).await
+                    .and_then(|x|if x.header.status.is_success() {Ok(x.body)}else{Err(anyhow::anyhow!("status {}",x.header.status))})
+            }
+            _ => tokio::fs::read(key.as_str()).await.map(Bytes::from).map_err(anyhow::Error::from),
+        };
+        let jwks = content.and_then(|x|Jwks::parse(String::from_utf8_lossy(&x).as_ref()));
+        match jwks {
+            Ok(o) => {
+                let jwks = Arc::new(o);
+                self.cache.lock().unwrap().insert(key,(Instant::now(),jwks.clone()));
+                Some(jwks)
+            }
+            Err(e) => {
+                wd_log::log_error_ln!("load jwks[{}] failed:{}",key,e);
+                //keep the old keys, and retry after a while
+                let (_,jwks) = cached?;
+                let at = Instant::now().checked_sub(self.ttl.saturating_sub(JWKS_REFRESH_MIN)).unwrap_or_else(Instant::now);
+                self.cache.lock().unwrap().insert(key,(at,jwks.clone()));
+                Some(jwks)
+            }
+        }
+    }
+}
+
+//rfc6750, no error code when the token is missing
+async fn unauthorized(session:&mut Session,error:Option<&str>,desc:&str)->Result<bool>{
+    let (challenge,body) = match error {
+        Some(e) => (
+            format!("Bearer error=\"{}\", error_description=\"{}\"",e,desc),
+            serde_json::json!({"error":e,"error_description":desc}),
+        ),
+        None => ("Bearer".to_string(),serde_json::json!({"error":"missing_token","error_description":desc})),
+    };
+    let headers = vec![
+        ("WWW-Authenticate".into(),challenge),
+        ("Content-Type".into(),"application/json".into()),
+    ];
+    write_response(session,401,headers,Some(Bytes::from(body.to_string()))).await
+}
+
+//the configured claim headers are always overwritten, so the client can not fake them
+pub fn set_claim_headers(req:&mut RequestHeader,ctx:&mut HttpProxyCtx)->Result<()>{
+    let jwt = if let Some(jwt) = ctx.service.as_ref().and_then(|x|x.policy.jwt.as_ref()){ jwt }else{ return Ok(()) };
+    for (_,name) in jwt.claim_headers.iter(){
+        req.remove_header(name.as_str());
+    }
+    for (k,v) in std::mem::take(&mut ctx.claim_headers){
+        if let Err(e) = req.append_header(k,v) {
+            wd_log::log_warn_ln!("skip invalid claim header:{}",e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use crate::pkg::annotation::{IngPolicy, JwksSource};
+    use crate::pkg::ingress::IngRule;
+    use crate::service::http_proxy::test::{request, start_proxy};
+
+    //the invalid jwks annotations deny the requests instead of turning the jwt off
+    #[tokio::test]
+    async fn test_invalid_jwt_denied(){
+        let cases = [
+            vec![("jwt-jwks-url","ftp://idp.test.com/jwks.json")],
+            vec![("jwt-jwks-url","https://idp.test.com/jwks.json"),("jwt-jwks-file","/etc/jwks.json")],
+            vec![("jwt-jwks-secret","kube-system/jwks")],
+        ];
+        for (i,case) in cases.iter().enumerate(){
+            let an = case.iter().map(|(k,v)|(format!("pingora.ingress/{}",k),v.to_string())).collect::<BTreeMap<_,_>>();
+            let (policy,errors) = IngPolicy::from_annotations(&an,"qa");
+            assert!(!errors.is_empty());
+            assert_eq!(policy.jwt.as_ref().map(|x|&x.jwks),Some(&JwksSource::Invalid));
+            let host = format!("jwt{}.test.com",i);
+            let rule = IngRule{path:"/".into(),ty:1,backend:"127.0.0.1".into(),port:1,..Default::default()}.set_policy(policy);
+            let addr = start_proxy(Default::default(),host.as_str(),vec![rule]).await;
+            let resp = request(addr,format!("GET / HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer a.b.c\r\n\r\n",host).as_str()).await;
+            assert!(resp.starts_with("http/1.1 500"),"{}",resp);
+        }
+    }
+}
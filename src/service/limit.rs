@@ -0,0 +1,49 @@
+use std::sync::Arc;
+use pingora::prelude::*;
+use crate::infra::rate_limit::{LocalRateLimiter, Quota, RateLimiter};
+use crate::pkg::annotation::IngLimitKey;
+use crate::service::http_proxy::HttpProxyCtx;
+use crate::service::response::write_response;
+
+pub struct RateLimitFilter{
+    limiter:Arc<dyn RateLimiter>,
+}
+
+impl Default for RateLimitFilter{
+    fn default() -> Self {
+        Self::new(Arc::new(LocalRateLimiter::default()))
+    }
+}
+
+impl RateLimitFilter{
+    pub fn new(limiter:Arc<dyn RateLimiter>)->Self{
+        Self{limiter}
+    }
+    //return true if the request is rejected and 429 has been responded
+    pub async fn filter(&self,session:&mut Session,ctx:&HttpProxyCtx)->Result<bool>{
+        let node = if let Some(ref s) = ctx.service{ s }else{ return Ok(false) };
+        let rl = if let Some(ref rl) = node.policy.rate_limit{ rl }else{ return Ok(false) };
+
+        let client_ip = || ctx.client_ip.map(|x|x.to_string()).unwrap_or_default();
+        let key = match rl.key {
+            IngLimitKey::Ip => client_ip(),
+            IngLimitKey::Path => session.req_header().uri.path().to_string(),
+            IngLimitKey::Header(ref name) => {
+                match session.req_header().headers.get(name.as_str()).and_then(|x|x.to_str().ok()) {
+                    Some(s) => s.to_string(),
+                    None => client_ip(), //requests without the header are limited by ip
+                }
+            }
+        };
+        let key = format!("{}{}|{}",node.host,node.path,key);
+
+        let wait = if let Some(wait) = self.limiter.acquire(key.as_str(),Quota::new(rl.rps,rl.burst)).await{
+            wait
+        }else{
+            return Ok(false)
+        };
+        wd_log::log_debug_ln!("rate limit reject key[{}] retry after {:?}",key,wait);
+        let retry_after = wait.as_secs() + if wait.subsec_nanos() > 0 {1}else{0};
+        write_response(session,429,vec![("Retry-After".into(),retry_after.max(1).to_string())],None).await
+    }
+}
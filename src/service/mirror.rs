@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use std::time::Duration;
+use bytes::Bytes;
+use pingora::http::RequestHeader;
+use pingora::prelude::*;
+use rand::Rng;
+use tokio::sync::Semaphore;
+use url::Url;
+use crate::infra::http_client::HttpClient;
+use crate::pkg::annotation::IngMirror;
+use crate::service::ext_auth::HOP_HEADERS;
+use crate::service::http_proxy::HttpProxyCtx;
+
+//the response is read to reuse the connection, a larger one is dropped
+const MIRROR_RESPONSE_BODY_MAX:usize = 64 * 1024;
+
+pub struct MirrorFilter{
+    client:Arc<HttpClient>,
+    //the mirror requests in flight, a slow target must not pile up the tasks
+    limit:Arc<Semaphore>,
+}
+
+impl MirrorFilter{
+    pub fn new(timeout:Duration,max_concurrency:usize)->Self{
+        Self{client:Arc::new(HttpClient::new(timeout)),limit:Arc::new(Semaphore::new(max_concurrency))}
+    }
+    //the request passed the filters is sampled by the percentage of the route
+    pub fn sample(ctx:&HttpProxyCtx)->bool{
+        match ctx.service.as_ref().and_then(|x|x.policy.mirror.as_ref()) {
+            Some(m) => m.percentage > 0 && rand::thread_rng().gen_range(0..100) < m.percentage,
+            None => false,
+        }
+    }
+    //called when the request is done, the whole body has been buffered by pingora
+    pub fn mirror(&self,session:&mut Session,ctx:&HttpProxyCtx){
+        let mirror = match ctx.service.as_ref().and_then(|x|x.policy.mirror.as_ref()) {
+            Some(o) => o,
+            None => return,
+        };
+        if !ctx.mirror || ctx.websocket.is_some() {
+            return
+        }
+        if !session.as_mut().is_body_done() || session.as_ref().retry_buffer_truncated() {
+            wd_log::log_debug_ln!("mirror[{}] skip the request, the body is not read or truncated",mirror.target);
+            return
+        }
+        let body = session.as_ref().get_retry_buffer().filter(|x|!x.is_empty());
+        if body.as_ref().map(|x|x.len()).unwrap_or(0) > mirror.body_max {
+            wd_log::log_debug_ln!("mirror[{}] skip the request, the body is over {}",mirror.target,mirror.body_max);
+            return
+        }
+        let (url,req) = match mirror_request(session.req_header(),mirror,ctx) {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_warn_ln!("mirror[{}] build request failed:{}",mirror.target,e);
+                return
+            }
+        };
+        self.send(url,req,body);
+    }
+    //false when the mirror requests in flight are full, the request is dropped
+    fn send(&self,url:Url,req:RequestHeader,body:Option<Bytes>)->bool{
+        let permit = match self.limit.clone().try_acquire_owned() {
+            Ok(o) => o,
+            Err(_) => {
+                wd_log::log_debug_ln!("mirror[{}] skip the request, too many mirror requests in flight",url);
+                return false
+            }
+        };
+        let client = self.client.clone();
+        tokio::spawn(async move{
+            if let Err(e) = client.send(&url,req,body,MIRROR_RESPONSE_BODY_MAX).await {
+                wd_log::log_debug_ln!("mirror[{}] request failed:{}",url,e);
+            }
+            drop(permit);
+        });
+        true
+    }
+}
+
+//the path of the request is appended to the path of the target, the Host of the request is sent as X-Forwarded-Host
+fn mirror_request(origin:&RequestHeader,mirror:&IngMirror,ctx:&HttpProxyCtx)->Result<(Url,RequestHeader)>{
+    let mut url = mirror.target.clone();
+    let path = format!("{}{}",mirror.target.path().trim_end_matches('/'),origin.uri.path());
+    url.set_path(path.as_str());
+    url.set_query(origin.uri.query());
+    let mut req = RequestHeader::build(origin.method.clone(),b"/",Some(origin.headers.len()+2))?;
+    for (k,v) in origin.headers.iter(){
+        if k == http::header::HOST || HOP_HEADERS.contains(&k.as_str()) {
+            continue
+        }
+        req.append_header(k.clone(),v)?;
+    }
+    if let Some(host) = origin.headers.get("Host"){
+        req.insert_header("X-Forwarded-Host",host)?;
+    }
+    if let Some(ip) = ctx.client_ip{
+        req.insert_header("X-Real-IP",ip.to_string())?;
+    }
+    Ok((url,req))
+}
+
+#[cfg(test)]
+mod test{
+    use std::time::Duration;
+    use bytes::Bytes;
+    use pingora::http::RequestHeader;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::pkg::annotation::IngMirror;
+    use crate::service::http_proxy::HttpProxyCtx;
+    use crate::service::mirror::{mirror_request, MirrorFilter};
+
+    #[tokio::test]
+    async fn test_mirror_request(){
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender,receiver) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move{
+            let (mut stream,_) = listener.accept().await.unwrap();
+            let mut req = vec![];
+            let mut buf = vec![0u8;1024];
+            while !req.ends_with(b"{}") {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0);
+                req.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            sender.send(String::from_utf8_lossy(&req).to_lowercase()).unwrap();
+        });
+
+        let mirror = IngMirror{target:format!("http://127.0.0.1:{}/shadow/",addr.port()).parse().unwrap(),percentage:100,body_max:1024};
+        let mut origin = RequestHeader::build("POST",b"/api/users?a=1",None).unwrap();
+        origin.insert_header("Host","example.com").unwrap();
+        origin.insert_header("Transfer-Encoding","chunked").unwrap();
+        origin.insert_header("X-Trace","t1").unwrap();
+        let (url,req) = mirror_request(&origin,&mirror,&HttpProxyCtx::default()).unwrap();
+        assert_eq!(url.path(),"/shadow/api/users");
+        assert_eq!(url.query(),Some("a=1"));
+
+        let filter = MirrorFilter::new(Duration::from_secs(3),1);
+        assert!(filter.send(url.clone(),req.clone(),Some(Bytes::from_static(b"{}"))));
+        //the mirror request in flight holds the only permit
+        assert!(!filter.send(url.clone(),req.clone(),None));
+        let req = receiver.await.unwrap();
+        assert!(req.starts_with("post /shadow/api/users?a=1 http/1.1\r\n"));
+        assert!(req.contains("x-forwarded-host: example.com"));
+        assert!(req.contains("x-trace: t1"));
+        assert!(!req.contains("chunked"));
+        assert!(req.ends_with("\r\n\r\n{}"));
+    }
+}
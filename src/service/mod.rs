@@ -1,10 +1,31 @@
 pub mod http_proxy;
 mod config;
+mod access;
+mod auth;
+mod canary;
+mod cors;
+mod ext_auth;
+mod jwt;
+mod forwarded;
+mod grpc;
+mod headers;
+mod limit;
+mod mirror;
+mod passthrough;
+mod redirect;
+mod response;
+mod rewrite;
+mod route_match;
+mod stream_proxy;
+mod tls;
+mod websocket;
 
 use pingora::prelude::*;
+use wd_tools::sync::Acl;
 use http_proxy::*;
-use crate::pkg::ingress;
+use crate::pkg::{gateway, ingress, leader, secret};
 use crate::service::config::Config;
+use crate::service::stream_proxy::{StreamProtocol, StreamProxyControl};
 
 pub fn start_pingora(){
     //监听pod
@@ -12,12 +33,56 @@ pub fn start_pingora(){
         .enable_all()
         .build().unwrap();
     let (hpc,cfg) = rt.block_on(async {
+        let base = Config::from_pod().await;
+        //started before any status write, the replica is not the leader until it holds the lease
+        if !base.leader_election_lease.is_empty() {
+            if let Err(e) = leader::LeaderElection::start(base.leader_election_lease.as_str(),base.lease_timing()).await{
+                wd_log::log_error_ln!("start leader election failed, the status and events are not written:{}",e);
+            }
+        }
+        let cfg = Acl::new(base.load_ingress_config().await);
+        if !base.ingress_config.is_empty() {
+            if let Err(e) = Config::start_watch(base,cfg.clone()).await{
+                wd_log::log_error_ln!("watch PingoraIngressConfig failed:{}",e);
+            }
+        }
+        //the listeners and watchers are started with this config, the live fields are read from the acl
+        let start = cfg.share();
+        start.apply_log_level();
         let recv = ingress::WatchIngress::default()
             .add_label_selector("control-class", "pingora")
             .start_watch().await.unwrap();
-        let hpc = HttpProxyControl::new_ing_event_watch(recv).await;
-        let cfg = Config::from_pod().await;
-        (hpc,cfg)
+        //the routes of gateway api share the router with ingress by one channel
+        let recv = if start.gateway_api {
+            let (sender,merged) = async_channel::bounded(8);
+            let ing_sender = sender.clone();
+            tokio::spawn(async move{
+                while let Ok(e) = recv.recv().await{
+                    if ing_sender.send(e).await.is_err() {
+                        return
+                    }
+                }
+            });
+            gateway::WatchGateway::start_watch(sender).await.unwrap();
+            merged
+        }else{
+            recv
+        };
+        let secrets = secret::SecretStore::default().start_watch().await.unwrap();
+        let hpc = HttpProxyControl::new_ing_event_watch(recv,cfg.clone(),secrets).await;
+        let streams = [(StreamProtocol::Tcp,&start.tcp_services_configmap),(StreamProtocol::Udp,&start.udp_services_configmap)];
+        for (proto,cm) in streams{
+            if cm.is_empty() {
+                continue
+            }
+            if let Err(e) = StreamProxyControl::start_watch(proto,cm,cfg.clone()).await{
+                wd_log::log_error_ln!("start {:?} services[{}] failed:{}",proto,cm,e);
+            }
+        }
+        if start.passthrough_port > 0 {
+            tokio::spawn(hpc.passthrough_proxy().serve(start.passthrough_port as u16));
+        }
+        (hpc,start)
     });
 
     wd_log::log_info_ln!("config=>{}",cfg.json());
@@ -25,10 +90,22 @@ pub fn start_pingora(){
     let mut my_server = Server::new(Some(Opt::default())).unwrap();
     my_server.bootstrap();
 
+    let tls = if cfg.https_port > 0 {
+        Some(hpc.tls_settings().unwrap())
+    }else{None};
     let mut gateway = http_proxy_service(&my_server.configuration,hpc);
     gateway.add_tcp(format!("0.0.0.0:{}",cfg.port).as_str());
+    if let Some(tls) = tls {
+        gateway.add_tls_with_settings(format!("0.0.0.0:{}",cfg.https_port).as_str(),None,tls);
+    }
 
     my_server.add_service(gateway);
 
+    if cfg.metrics_port > 0 {
+        let mut metrics = pingora::services::listening::Service::prometheus_http_service();
+        metrics.add_tcp(format!("0.0.0.0:{}",cfg.metrics_port).as_str());
+        my_server.add_service(metrics);
+    }
+
     my_server.run_forever();
 }
\ No newline at end of file
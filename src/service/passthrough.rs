@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use wd_tools::sync::Acl;
+use crate::infra::sni::{ClientHello, parse_client_hello};
+use crate::service::config::Config;
+use crate::service::http_proxy::Router;
+use crate::service::tls::find_exact_host;
+
+const CLIENT_HELLO_MAX:usize = 16 * 1024 + 5;
+const CLIENT_HELLO_TIMEOUT:Duration = Duration::from_secs(5);
+
+//the tls listener in front of the https listener, the host with ssl passthrough is forwarded to its backend,
+//the others are forwarded to the local https listener
+pub struct PassthroughProxy{
+    router:Acl<HashMap<String,Router>>,
+    cfg:Acl<Config>,
+    //the https listener bound at start
+    https_port:i32,
+    clients:PassthroughClients,
+}
+
+//the clients of the connections forwarded to the local https listener, keyed by the local address of the forwarding connection.
+//the https listener looks up the client of a loopback peer here, or the client ip would be 127.0.0.1
+#[derive(Clone,Default)]
+pub struct PassthroughClients(Arc<Mutex<HashMap<SocketAddr,SocketAddr>>>);
+
+impl PassthroughClients{
+    fn insert(&self,local:SocketAddr,client:SocketAddr){
+        self.0.lock().unwrap().insert(local,client);
+    }
+    fn remove(&self,local:&SocketAddr){
+        self.0.lock().unwrap().remove(local);
+    }
+    //the client of the peer forwarded by the passthrough listener, other peers are returned as is
+    pub fn resolve(&self,peer:SocketAddr)->SocketAddr{
+        if !peer.ip().is_loopback() {
+            return peer
+        }
+        self.0.lock().unwrap().get(&peer).copied().unwrap_or(peer)
+    }
+}
+
+impl PassthroughProxy{
+    pub fn new(router:Acl<HashMap<String,Router>>,cfg:Acl<Config>,clients:PassthroughClients)->Self{
+        let https_port = cfg.share().https_port;
+        Self{router,cfg,https_port,clients}
+    }
+    pub async fn serve(self,port:u16){
+        let listener = match TcpListener::bind(("0.0.0.0",port)).await {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_error_ln!("passthrough bind port[{}] failed:{}",port,e);
+                return
+            }
+        };
+        let this = Arc::new(self);
+        loop {
+            let (conn,peer) = match listener.accept().await {
+                Ok(o) => o,
+                Err(e) => {
+                    wd_log::log_warn_ln!("passthrough accept error:{}",e);
+                    continue
+                }
+            };
+            let this = this.clone();
+            tokio::spawn(async move{
+                if let Err(e) = this.proxy(conn,peer).await{
+                    wd_log::log_debug_ln!("passthrough client[{}] error:{}",peer,e);
+                }
+            });
+        }
+    }
+    fn backend(&self,host:Option<&str>)->Option<String>{
+        if let Some(s) = host.and_then(|h|find_exact_host(&self.router.share(),h,|r|r.passthrough.clone())){
+            return Some(s)
+        }
+        self.local_https()
+    }
+    fn local_https(&self)->Option<String>{
+        if self.https_port > 0 {
+            Some(format!("127.0.0.1:{}",self.https_port))
+        }else{
+            None
+        }
+    }
+    async fn proxy(&self,mut conn:TcpStream,peer:SocketAddr)->anyhow::Result<()>{
+        let mut buf = Vec::with_capacity(4096);
+        let host = tokio::time::timeout(CLIENT_HELLO_TIMEOUT,read_client_hello(&mut conn,&mut buf)).await??;
+        let backend = self.backend(host.as_deref()).ok_or_else(||anyhow::anyhow!("host[{:?}] has no backend",host))?;
+        wd_log::log_debug_ln!("passthrough host[{:?}] to [{}]",host,backend);
+        let mut upstream = match self.cfg.share().proxy_connect_timeout {
+            0 => TcpStream::connect(backend.as_str()).await?,
+            n => tokio::time::timeout(Duration::from_secs(n),TcpStream::connect(backend.as_str())).await??,
+        };
+        upstream.set_nodelay(true)?;
+        //the client is recorded before the client hello is sent, the https listener reads it after the handshake
+        let local = if Some(&backend) == self.local_https().as_ref() {
+            let addr = upstream.local_addr()?;
+            self.clients.insert(addr,peer);
+            Some(addr)
+        }else{
+            None
+        };
+        let result = async {
+            upstream.write_all(&buf).await?;
+            tokio::io::copy_bidirectional(&mut conn,&mut upstream).await?;
+            Ok(())
+        }.await;
+        if let Some(ref addr) = local {
+            self.clients.remove(addr);
+        }
+        result
+    }
+}
+
+//read until the client hello is complete, the data is kept in buf and sent to the backend later
+async fn read_client_hello(conn:&mut TcpStream,buf:&mut Vec<u8>)->anyhow::Result<Option<String>>{
+    loop {
+        match parse_client_hello(buf) {
+            ClientHello::Complete(s) => return Ok(s),
+            //not a tls client hello, let the https listener respond it
+            ClientHello::Invalid => return Ok(None),
+            ClientHello::Incomplete => {
+                if buf.len() >= CLIENT_HELLO_MAX {
+                    return Err(anyhow::anyhow!("client hello too large"))
+                }
+                if conn.read_buf(buf).await? == 0 {
+                    return Err(anyhow::anyhow!("connection closed before client hello"))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpSocket;
+    use wd_tools::sync::Acl;
+    use crate::pkg::annotation::IngPolicy;
+    use crate::pkg::ingress::{IngHost, IngressEvent, IngRule};
+    use crate::service::config::Config;
+    use crate::service::http_proxy::Router;
+    use crate::service::http_proxy::test::{connect, free_addr, new_control, read_head, serve};
+    use crate::service::passthrough::PassthroughProxy;
+
+    #[test]
+    fn test_passthrough_backend(){
+        let mut rule = IngRule{path:"/".into(),ty:1,backend:"mtls".into(),port:8443,owner:"qa/mtls".into(),..Default::default()};
+        rule.policy.backend.ssl_passthrough = true;
+        let mut router = Router::from_host("mtls.test.com");
+        router.update_from_ing_rule(vec![rule.clone()]);
+        let mut wildcard = Router::from_host("*.wild.test.com");
+        wildcard.update_from_ing_rule(vec![rule.clone()]);
+        //the update without ssl-passthrough resets it
+        let mut off = Router::from_host("off.test.com");
+        off.update_from_ing_rule(vec![rule.clone()]);
+        rule.policy.backend.ssl_passthrough = false;
+        off.update_from_ing_rule(vec![rule]);
+        assert!(off.passthrough.is_none());
+        let mut routers = HashMap::new();
+        routers.insert("mtls.test.com".to_string(),router);
+        routers.insert("*.wild.test.com".to_string(),wildcard);
+        routers.insert("off.test.com".to_string(),off);
+        routers.insert("test.com".to_string(),Router::from_host("test.com"));
+
+        let pp = PassthroughProxy::new(Acl::new(routers),Acl::new(Config::default()),Default::default());
+        assert_eq!(pp.backend(Some("mtls.test.com")),Some("mtls:8443".into()));
+        assert_eq!(pp.backend(Some("api.mtls.test.com")),Some("127.0.0.1:30443".into()));
+        assert_eq!(pp.backend(Some("api.wild.test.com")),Some("mtls:8443".into()));
+        assert_eq!(pp.backend(Some("wild.test.com")),Some("127.0.0.1:30443".into()));
+        assert_eq!(pp.backend(Some("off.test.com")),Some("127.0.0.1:30443".into()));
+        assert_eq!(pp.backend(Some("test.com")),Some("127.0.0.1:30443".into()));
+        assert_eq!(pp.backend(None),Some("127.0.0.1:30443".into()));
+    }
+
+    //the connection forwarded to the https listener keeps the client ip, a trusted loopback does not trust the client.
+    //the forwarded listener is a plain one, the request which is not a client hello is forwarded to it as well
+    #[tokio::test]
+    async fn test_passthrough_client_ip(){
+        let an = BTreeMap::from([("pingora.ingress/denylist-source-range".to_string(),"127.0.0.2".to_string())]);
+        let (policy,_) = IngPolicy::from_annotations(&an,"qa");
+        let rule = IngRule{path:"/".into(),ty:1,backend:"127.0.0.1".into(),port:1,..Default::default()}.set_policy(policy);
+        let hosts = vec![IngHost{host:"pt-client.test.com".into(),rules:vec![rule]}];
+        let https = free_addr();
+        let cfg = Config{https_port:https.port() as i32,trusted_proxies:vec!["127.0.0.1".into()],..Default::default()};
+        let hpc = new_control(cfg,IngressEvent{ty:1,default_backend:None,hosts,sni:Default::default(),ing:None,reports:vec![]}).await;
+        let passthrough = free_addr();
+        tokio::spawn(hpc.passthrough_proxy().serve(passthrough.port()));
+        serve(hpc,https);
+        drop(connect(https).await);
+
+        for xff in ["","X-Forwarded-For: 10.0.0.1\r\n"]{
+            let mut stream = loop {
+                let socket = TcpSocket::new_v4().unwrap();
+                socket.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+                match socket.connect(passthrough).await {
+                    Ok(o) => break o,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+                }
+            };
+            stream.write_all(format!("GET / HTTP/1.1\r\nHost: pt-client.test.com\r\n{}\r\n",xff).as_bytes()).await.unwrap();
+            let resp = read_head(&mut stream).await;
+            assert!(resp.starts_with("http/1.1 403"),"{}",resp);
+        }
+    }
+}
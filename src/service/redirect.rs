@@ -0,0 +1,110 @@
+use pingora::prelude::*;
+use crate::pkg::annotation::{IngRedirect, IngWwwRedirect};
+use crate::infra::ip::CidrSet;
+use crate::service::forwarded::request_is_https;
+use crate::service::http_proxy::HttpProxyCtx;
+use crate::service::response::write_response;
+
+//return true if the request is redirected
+pub async fn redirect_filter(session:&mut Session,ctx:&HttpProxyCtx,trusted:&CidrSet,https_port:u64)->Result<bool>{
+    let node = if let Some(ref s) = ctx.service{ s }else{ return Ok(false) };
+    let req = session.req_header();
+    let host = req.headers.get("Host").and_then(|x|x.to_str().ok()).unwrap_or_default();
+    let uri = req.uri.path_and_query().map(|x|x.as_str()).unwrap_or("/");
+    let tls = request_is_https(session,ctx,trusted);
+    let (code,location) = if let Some(s) = redirect_location(&node.policy.redirect,host,uri,tls,!ctx.sni.is_empty(),https_port){ s }else{ return Ok(false) };
+    wd_log::log_debug_ln!("redirect [{}{}] to [{}] with {}",host,uri,location,code);
+    write_response(session,code,vec![("Location".into(),location)],None).await
+}
+
+fn redirect_location(redirect:&IngRedirect,host:&str,uri:&str,tls:bool,host_has_tls:bool,https_port:u64)->Option<(u16,String)>{
+    if let Some(ref u) = redirect.permanent{
+        return Some((301,u.to_string()))
+    }
+    if let Some(ref u) = redirect.temporal{
+        return Some((302,u.to_string()))
+    }
+    let to_https = redirect.ssl_redirect && host_has_tls && !tls;
+    let (name,port) = match host.rsplit_once(':') {
+        Some((n,p)) if !p.is_empty() && p.chars().all(|c|c.is_ascii_digit()) => (n,Some(p)),
+        _ => (host,None),
+    };
+    let www_host = match redirect.www {
+        Some(IngWwwRedirect::Add) if !name.starts_with("www.") => Some(format!("www.{}",name)),
+        Some(IngWwwRedirect::Strip) => name.strip_prefix("www.").map(|x|x.to_string()),
+        _ => None,
+    };
+    if !to_https && www_host.is_none() {
+        return None
+    }
+    let scheme = if to_https || tls {"https"}else{"http"};
+    let mut location = format!("{}://{}",scheme,www_host.as_deref().unwrap_or(name));
+    //the port of http is not the port of https
+    match (to_https,port) {
+        (true,_) if https_port != 443 => location.push_str(format!(":{}",https_port).as_str()),
+        (false,Some(p)) => {
+            location.push(':');
+            location.push_str(p);
+        }
+        _ => {}
+    }
+    location.push_str(uri);
+    let code = if to_https {308}else{301};
+    Some((code,location))
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::HashMap;
+    use crate::pkg::annotation::{IngRedirect, IngWwwRedirect};
+    use crate::pkg::ingress::{IngHost, IngressEvent, IngRule, IngSni};
+    use crate::service::config::Config;
+    use crate::service::http_proxy::test::{request, start_proxy_with};
+    use crate::service::redirect::redirect_location;
+
+    #[test]
+    fn test_redirect_location(){
+        let mut r = IngRedirect{ssl_redirect:true,..Default::default()};
+        assert_eq!(redirect_location(&r,"test.com:8080","/a?b=1",false,true,443),Some((308,"https://test.com/a?b=1".into())));
+        assert_eq!(redirect_location(&r,"test.com","/a",true,true,443),None);
+        assert_eq!(redirect_location(&r,"test.com","/a",false,false,443),None);
+
+        assert_eq!(redirect_location(&r,"test.com:8080","/a",false,true,8443),Some((308,"https://test.com:8443/a".into())));
+
+        r.www = Some(IngWwwRedirect::Add);
+        assert_eq!(redirect_location(&r,"test.com","/a",true,true,443),Some((301,"https://www.test.com/a".into())));
+        assert_eq!(redirect_location(&r,"test.com","/a",false,true,443),Some((308,"https://www.test.com/a".into())));
+        assert_eq!(redirect_location(&r,"www.test.com:8080","/a",false,false,443),None);
+        r.www = Some(IngWwwRedirect::Strip);
+        assert_eq!(redirect_location(&r,"www.test.com:8080","/a",false,false,443),Some((301,"http://test.com:8080/a".into())));
+
+        r.permanent = Some("https://other.com/".parse().unwrap());
+        assert_eq!(redirect_location(&r,"test.com","/a",false,false,443),Some((301,"https://other.com/".into())));
+        r.permanent = None;
+        r.temporal = Some("https://other.com/b".parse().unwrap());
+        assert_eq!(redirect_location(&r,"test.com","/a",false,false,443),Some((302,"https://other.com/b".into())));
+    }
+
+    //the tls terminated by a trusted load balancer is not redirected again
+    #[tokio::test]
+    async fn test_ssl_redirect_behind_proxy(){
+        let start = |trusted:Vec<String>,host:&str|{
+            let cfg = Config{trusted_proxies:trusted,ssl_redirect_port:8443,..Default::default()};
+            let mut rule = IngRule{path:"/".into(),ty:1,backend:"127.0.0.1".into(),port:1,..Default::default()};
+            rule.policy.redirect.ssl_redirect = true;
+            let sni = IngSni{sni:HashMap::from([(host.to_string(),"tls".to_string())]),certs:HashMap::new()};
+            let event = IngressEvent{ty:1,default_backend:None,hosts:vec![IngHost{host:host.into(),rules:vec![rule]}],sni,ing:None,reports:vec![]};
+            start_proxy_with(cfg,event)
+        };
+        let addr = start(vec!["127.0.0.1/32".into()],"lb.test.com").await;
+        let resp = request(addr,"GET /a HTTP/1.1\r\nHost: lb.test.com\r\nX-Forwarded-Proto: https\r\n\r\n").await;
+        assert!(!resp.starts_with("http/1.1 308"),"{}",resp);
+        let resp = request(addr,"GET /a HTTP/1.1\r\nHost: lb.test.com\r\nX-Forwarded-Proto: http\r\n\r\n").await;
+        assert!(resp.starts_with("http/1.1 308") && resp.contains("location: https://lb.test.com:8443/a\r\n"),"{}",resp);
+
+        //the header of an untrusted client is ignored
+        let addr = start(vec![],"direct.test.com").await;
+        let resp = request(addr,"GET /a HTTP/1.1\r\nHost: direct.test.com\r\nX-Forwarded-Proto: https\r\n\r\n").await;
+        assert!(resp.starts_with("http/1.1 308"),"{}",resp);
+    }
+}
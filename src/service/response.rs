@@ -0,0 +1,25 @@
+use bytes::Bytes;
+use pingora::http::ResponseHeader;
+use pingora::prelude::*;
+use crate::service::grpc::{is_grpc_request, write_grpc_error};
+
+//write a response generated by gateway, the request will not be sent to upstream.
+//the return value can be returned by request_filter directly
+pub async fn write_response(session:&mut Session,code:u16,headers:Vec<(String,String)>,body:Option<Bytes>)->Result<bool>{
+    //the grpc client can only read the grpc status
+    if code >= 400 && is_grpc_request(session) {
+        write_grpc_error(session,code).await?;
+        return Ok(true)
+    }
+    let body = body.unwrap_or_default();
+    let mut resp = ResponseHeader::build(code,Some(headers.len()+1))?;
+    for (k,v) in headers{
+        resp.append_header(k,v)?;
+    }
+    resp.insert_header("Content-Length",body.len().to_string())?;
+    session.write_response_header(Box::new(resp)).await?;
+    if !body.is_empty() {
+        session.write_response_body(body).await?;
+    }
+    Ok(true)
+}
@@ -0,0 +1,90 @@
+use pingora::http::RequestHeader;
+use pingora::prelude::*;
+use crate::pkg::annotation::IngRewrite;
+use crate::service::http_proxy::{HttpProxyCtx, RouterNode};
+
+//return the new path of upstream request, none means not changed
+pub fn rewrite_path(node:&RouterNode,path:&str)->Option<String>{
+    let rewrite = node.policy.rewrite.as_ref()?;
+    if let Some(ref regex) = node.regex{
+        let caps = regex.captures(path)?;
+        let mut dst = String::new();
+        match rewrite {
+            IngRewrite::Target(target) => caps.expand(target.as_str(),&mut dst),
+            IngRewrite::StripPrefix => dst.push_str(&path[caps.get(0)?.end()..]),
+        }
+        return Some(ensure_slash(dst))
+    }
+    //the default backend has no path
+    if node.path.is_empty() || !path.starts_with(node.path.as_str()) {
+        return None
+    }
+    let rest = &path[node.path.len()..];
+    let target = match rewrite {
+        IngRewrite::Target(target) => target.as_str(),
+        IngRewrite::StripPrefix => "",
+    };
+    //prefix matches by path element
+    if !rest.is_empty() && !rest.starts_with('/') && !node.path.ends_with('/') {
+        return None
+    }
+    let rest = rest.trim_start_matches('/');
+    if rest.is_empty() {
+        return Some(ensure_slash(target.to_string()))
+    }
+    Some(format!("{}/{}",target.trim_end_matches('/'),rest))
+}
+
+fn ensure_slash(path:String)->String{
+    if path.starts_with('/') { path }else{ format!("/{}",path) }
+}
+
+pub fn set_rewrite_path(req:&mut RequestHeader,ctx:&HttpProxyCtx)->Result<()>{
+    let node = if let Some(ref s) = ctx.service{ s }else{ return Ok(()) };
+    let path = if let Some(s) = rewrite_path(node,req.uri.path()){ s }else{ return Ok(()) };
+    let uri = match req.uri.query() {
+        Some(q) => format!("{}?{}",path,q),
+        None => path,
+    };
+    wd_log::log_debug_ln!("rewrite path[{}] to [{}]",req.uri.path(),uri);
+    let uri = uri.parse().or_else(|e|Error::e_explain(ErrorType::InvalidHTTPHeader,format!("rewrite uri:{}",e)))?;
+    req.set_uri(uri);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test{
+    use crate::pkg::annotation::IngRewrite;
+    use crate::pkg::ingress::IngRule;
+    use crate::service::http_proxy::RouterNode;
+    use crate::service::rewrite::rewrite_path;
+
+    fn node(path:&str,ty:u8,rewrite:IngRewrite)->RouterNode{
+        let mut rule = IngRule{path:path.into(),ty,backend:"echo".into(),port:80,..Default::default()};
+        rule.policy.rewrite = Some(rewrite);
+        RouterNode::from(rule)
+    }
+
+    #[test]
+    fn test_rewrite_path(){
+        let n = node("/api",1,IngRewrite::StripPrefix);
+        assert_eq!(rewrite_path(&n,"/api/user"),Some("/user".into()));
+        assert_eq!(rewrite_path(&n,"/api"),Some("/".into()));
+        assert_eq!(rewrite_path(&n,"/apiv2"),None);
+
+        let n = node("/api/",1,IngRewrite::Target("/v1".into()));
+        assert_eq!(rewrite_path(&n,"/api/user"),Some("/v1/user".into()));
+        assert_eq!(rewrite_path(&n,"/api/"),Some("/v1".into()));
+
+        let n = node("/api",2,IngRewrite::Target("/health".into()));
+        assert_eq!(rewrite_path(&n,"/api"),Some("/health".into()));
+
+        let n = node("/api(/|$)(.*)",3,IngRewrite::Target("/v2/$2".into()));
+        assert_eq!(rewrite_path(&n,"/api/user/1"),Some("/v2/user/1".into()));
+        assert_eq!(rewrite_path(&n,"/api"),Some("/v2/".into()));
+        assert_eq!(rewrite_path(&n,"/other"),None);
+
+        let n = node("/static/[a-z]+",3,IngRewrite::StripPrefix);
+        assert_eq!(rewrite_path(&n,"/static/js/app.js"),Some("/app.js".into()));
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+use pingora::http::RequestHeader;
+use regex::Regex;
+use crate::pkg::ingress::{IngConditions, IngValueMatch};
+use crate::service::canary::overlay;
+use crate::service::http_proxy::RouterNode;
+
+#[derive(Debug,Clone)]
+enum ValueMatcher{
+    Exact(String),
+    //the whole value is matched, an invalid regex matches nothing
+    Regex(Option<Regex>),
+}
+
+impl ValueMatcher{
+    fn is_match(&self,value:&str)->bool{
+        match self {
+            ValueMatcher::Exact(s) => s == value,
+            ValueMatcher::Regex(r) => r.as_ref().map(|x|x.is_match(value)).unwrap_or(false),
+        }
+    }
+}
+
+impl From<&IngValueMatch> for ValueMatcher{
+    fn from(value: &IngValueMatch) -> Self {
+        if value.regex {
+            ValueMatcher::Regex(Regex::new(format!("^(?:{})$",value.value).as_str()).ok())
+        }else{
+            ValueMatcher::Exact(value.value.clone())
+        }
+    }
+}
+
+//the compiled conditions of a router node
+#[derive(Debug,Clone,Default)]
+pub struct RouteMatcher{
+    headers:Vec<(String,ValueMatcher)>,
+    query:Vec<(String,ValueMatcher)>,
+    method:Option<String>,
+}
+
+impl From<&IngConditions> for RouteMatcher{
+    fn from(value: &IngConditions) -> Self {
+        Self{
+            headers: value.headers.iter().map(|x|(x.name.clone(),ValueMatcher::from(x))).collect(),
+            query: value.query.iter().map(|x|(x.name.clone(),ValueMatcher::from(x))).collect(),
+            method: value.method.as_ref().map(|x|x.to_uppercase()),
+        }
+    }
+}
+
+impl RouteMatcher{
+    pub fn is_match(&self,req:&RequestHeader)->bool{
+        if let Some(ref m) = self.method{
+            if req.method.as_str() != m.as_str() {
+                return false
+            }
+        }
+        for (name,m) in self.headers.iter(){
+            if !req.headers.get_all(name.as_str()).iter().any(|x|x.to_str().map(|x|m.is_match(x)).unwrap_or(false)) {
+                return false
+            }
+        }
+        if self.query.is_empty() {
+            return true
+        }
+        let query = url::form_urlencoded::parse(req.uri.query().unwrap_or_default().as_bytes()).collect::<Vec<_>>();
+        self.query.iter().all(|(name,m)|{
+            //the first value is used when the key repeats
+            query.iter().find(|(k,_)|k == name).map(|(_,v)|m.is_match(v)).unwrap_or(false)
+        })
+    }
+}
+
+//the more specific conditions are tried first: method, the count of header matches, then query matches
+fn specificity(c:&IngConditions)->(bool,usize,usize){
+    (c.method.is_some(),c.headers.len(),c.query.len())
+}
+
+//the nodes of the same path are merged into one, the node without conditions holds the others as alternatives.
+//a node with the same conditions is replaced, or overlaid by the canary
+pub fn merge_node(old:Option<Arc<RouterNode>>,new:RouterNode)->Arc<RouterNode>{
+    let mut list = vec![];
+    if let Some(old) = old {
+        let mut old = (*old).clone();
+        list.extend(std::mem::take(&mut old.alternatives).into_iter().map(|x|(*x).clone()));
+        list.push(old);
+    }
+    let new = match list.iter().position(|x|x.conditions == new.conditions) {
+        Some(i) => overlay(list.remove(i),new),
+        None => new,
+    };
+    list.push(new);
+    list.sort_by_key(|x|std::cmp::Reverse(specificity(&x.conditions)));
+    let mut primary = list.pop().unwrap();
+    primary.alternatives = list.into_iter().map(Arc::new).collect();
+    Arc::new(primary)
+}
+
+//the first node whose conditions are matched
+pub fn select_node(node:&Arc<RouterNode>,req:&RequestHeader)->Option<Arc<RouterNode>>{
+    node.alternatives.iter().chain(std::iter::once(node))
+        .find(|x|x.matcher.is_match(req))
+        .cloned()
+}
+
+#[cfg(test)]
+mod test{
+    use pingora::http::RequestHeader;
+    use crate::pkg::ingress::{IngConditions, IngRule, IngValueMatch};
+    use crate::service::http_proxy::RouterNode;
+    use crate::service::route_match::{merge_node, select_node};
+
+    fn node(backend:&str,conditions:IngConditions)->RouterNode{
+        RouterNode::from(IngRule{path:"/api".into(),ty:1,backend:backend.into(),port:80,conditions,..Default::default()})
+    }
+
+    #[test]
+    fn test_merge_and_select(){
+        let header = IngConditions{headers:vec![IngValueMatch{name:"x-version".into(),value:"v2".into(),regex:false}],..Default::default()};
+        let query = IngConditions{
+            headers:vec![IngValueMatch{name:"x-version".into(),value:"v[0-9]+".into(),regex:true}],
+            query:vec![IngValueMatch{name:"debug".into(),value:"1".into(),regex:false}],
+            method:None,
+        };
+        let n = merge_node(None,node("v2",header.clone()));
+        let n = merge_node(Some(n),node("v1",IngConditions::default()));
+        let n = merge_node(Some(n),node("debug",query));
+        assert_eq!(n.backend,"v1");
+        assert_eq!(n.alternatives.len(),2);
+        assert_eq!(n.alternatives[0].backend,"debug");
+
+        let mut req = RequestHeader::build("GET",b"/api/user?debug=1",None).unwrap();
+        assert_eq!(select_node(&n,&req).unwrap().backend,"v1");
+        req.insert_header("X-Version","v2").unwrap();
+        assert_eq!(select_node(&n,&req).unwrap().backend,"debug");
+        req.set_uri("/api/user".parse().unwrap());
+        assert_eq!(select_node(&n,&req).unwrap().backend,"v2");
+
+        //only the conditional nodes, no match
+        let n = merge_node(None,node("v2",header));
+        let req = RequestHeader::build("GET",b"/api/user",None).unwrap();
+        assert!(select_node(&n,&req).is_none());
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::task::JoinHandle;
+use wd_tools::sync::Acl;
+use crate::pkg::configmap::watch_config_map;
+use crate::service::config::Config;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum StreamProtocol{
+    Tcp,
+    Udp,
+}
+
+//nginx-ingress style tcp-services/udp-services: the key is the gateway port, the value is namespace/service:port.
+//return the port => backend address and the invalid items
+pub fn parse_stream_services(data:&BTreeMap<String,String>)->(HashMap<u16,String>,Vec<String>){
+    let mut services = HashMap::new();
+    let mut invalid = vec![];
+    for (k,v) in data.iter(){
+        let port = match k.trim().parse::<u16>() {
+            Ok(p) if p > 0 => p,
+            _ => {
+                invalid.push(format!("{}: invalid port",k));
+                continue
+            }
+        };
+        let backend = v.trim().split_once('/')
+            .and_then(|(ns,svc)|svc.split_once(':').map(|(name,port)|(ns,name,port)))
+            .filter(|(ns,name,_)|!ns.is_empty() && !name.is_empty())
+            .and_then(|(ns,name,port)|port.parse::<u16>().ok().map(|port|format!("{}.{}:{}",name,ns,port)));
+        match backend {
+            Some(s) => {
+                services.insert(port,s);
+            }
+            None => invalid.push(format!("{}: [{}] should be namespace/service:port",k,v)),
+        }
+    }
+    (services,invalid)
+}
+
+//the l4 listeners of one configmap, they are started and stopped with the configmap changes.
+//they are tokio tasks instead of pingora services, the services of pingora 0.1 are fixed before the server runs and have no udp
+pub struct StreamProxyControl{
+    proto:StreamProtocol,
+    cfg:Acl<Config>,
+    running:HashMap<u16,(Acl<String>,JoinHandle<()>)>,
+}
+
+impl StreamProxyControl{
+    pub async fn start_watch(proto:StreamProtocol,configmap:&str,cfg:Acl<Config>)->anyhow::Result<()>{
+        let recv = watch_config_map(configmap).await?;
+        let mut spc = Self{proto,cfg,running:HashMap::new()};
+        tokio::spawn(async move{
+            while let Ok(data) = recv.recv().await{
+                spc.update(data).await;
+            }
+            wd_log::log_info_ln!("{:?} services receiver channel over",spc.proto);
+        });
+        Ok(())
+    }
+    async fn update(&mut self,data:BTreeMap<String,String>){
+        let (mut services,mut invalid) = parse_stream_services(&data);
+        let cfg = self.cfg.share();
+        services.retain(|port,_|{
+            let used = [cfg.port,cfg.https_port,cfg.passthrough_port,cfg.metrics_port].contains(&(*port as i32));
+            if used {
+                invalid.push(format!("{}: conflicts with the port of gateway",port));
+            }
+            !used
+        });
+        if !invalid.is_empty() {
+            wd_log::log_warn_ln!("{:?} services skip invalid items:{:?}",self.proto,invalid);
+        }
+        self.running.retain(|port,(_,handle)|{
+            if services.contains_key(port) {
+                return true
+            }
+            wd_log::log_info_ln!("stop {:?} service port[{}]",self.proto,port);
+            handle.abort();
+            false
+        });
+        for (port,backend) in services{
+            //the udp backend is resolved here, not for every new client
+            let backend = match self.proto {
+                StreamProtocol::Tcp => backend,
+                StreamProtocol::Udp => match resolve(backend.as_str()).await {
+                    Ok(o) => o.to_string(),
+                    Err(e) => {
+                        wd_log::log_warn_ln!("udp service port[{}] backend[{}] resolve failed:{}",port,backend,e);
+                        continue
+                    }
+                },
+            };
+            //the backend of a running listener is replaced in place, the port is not rebound
+            if let Some((acl,_)) = self.running.get(&port){
+                if *acl.share() != backend {
+                    wd_log::log_info_ln!("update {:?} service port[{}] backend[{}]",self.proto,port,backend);
+                    acl.set(backend);
+                }
+                continue
+            }
+            wd_log::log_info_ln!("start {:?} service port[{}] backend[{}]",self.proto,port,backend);
+            let acl = Acl::new(backend);
+            let handle = match self.proto {
+                StreamProtocol::Tcp => tokio::spawn(serve_tcp(port,acl.clone(),self.cfg.clone())),
+                StreamProtocol::Udp => tokio::spawn(serve_udp(port,acl.clone(),self.cfg.clone())),
+            };
+            self.running.insert(port,(acl,handle));
+        }
+    }
+}
+
+async fn serve_tcp(port:u16,backend:Acl<String>,cfg:Acl<Config>){
+    let listener = match TcpListener::bind(("0.0.0.0",port)).await {
+        Ok(o) => o,
+        Err(e) => {
+            wd_log::log_error_ln!("tcp service bind port[{}] failed:{}",port,e);
+            return
+        }
+    };
+    loop {
+        let (conn,peer) = match listener.accept().await {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_warn_ln!("tcp service port[{}] accept error:{}",port,e);
+                continue
+            }
+        };
+        let backend = backend.share();
+        let connect_timeout = match cfg.share().proxy_connect_timeout {
+            0 => None,
+            n => Some(Duration::from_secs(n)),
+        };
+        tokio::spawn(async move{
+            if let Err(e) = proxy_tcp(conn,backend.as_str(),connect_timeout).await{
+                wd_log::log_debug_ln!("tcp service port[{}] client[{}] backend[{}] error:{}",port,peer,backend,e);
+            }
+        });
+    }
+}
+
+async fn proxy_tcp(mut conn:TcpStream,backend:&str,connect_timeout:Option<Duration>)->anyhow::Result<()>{
+    let mut upstream = match connect_timeout {
+        Some(t) => tokio::time::timeout(t,TcpStream::connect(backend)).await??,
+        None => TcpStream::connect(backend).await?,
+    };
+    upstream.set_nodelay(true)?;
+    tokio::io::copy_bidirectional(&mut conn,&mut upstream).await?;
+    Ok(())
+}
+
+//a client of the udp service, it has its own socket to the backend
+struct UdpSession{
+    upstream:UdpSocket,
+    //the last time the client sent, unit: millisecond from the start of listener
+    last:AtomicU64,
+}
+
+async fn serve_udp(port:u16,backend:Acl<String>,cfg:Acl<Config>){
+    let socket = match UdpSocket::bind(("0.0.0.0",port)).await {
+        Ok(o) => Arc::new(o),
+        Err(e) => {
+            wd_log::log_error_ln!("udp service bind port[{}] failed:{}",port,e);
+            return
+        }
+    };
+    let start = Instant::now();
+    let sessions:Arc<Mutex<HashMap<SocketAddr,Arc<UdpSession>>>> = Arc::default();
+    let mut buf = vec![0u8;65535];
+    loop {
+        let (n,peer) = match socket.recv_from(&mut buf).await {
+            Ok(o) => o,
+            Err(e) => {
+                wd_log::log_warn_ln!("udp service port[{}] recv error:{}",port,e);
+                continue
+            }
+        };
+        let (session,count) = {
+            let sessions = sessions.lock().unwrap();
+            (sessions.get(&peer).cloned(),sessions.len())
+        };
+        let max = cfg.share().udp_max_sessions;
+        if session.is_none() && count as u64 >= max {
+            wd_log::log_debug_ln!("udp service port[{}] drop client[{}], sessions over {}",port,peer,max);
+            continue
+        }
+        let session = match session {
+            Some(s) => s,
+            None => match udp_session(backend.share().as_str()).await {
+                Ok(s) => {
+                    let s = Arc::new(s);
+                    sessions.lock().unwrap().insert(peer,s.clone());
+                    //the timeout of config is read when the session starts
+                    let timeout = Duration::from_secs(cfg.share().udp_session_timeout.max(1));
+                    tokio::spawn(udp_reply(socket.clone(),peer,s.clone(),sessions.clone(),start,timeout));
+                    s
+                }
+                Err(e) => {
+                    wd_log::log_debug_ln!("udp service port[{}] client[{}] connect backend error:{}",port,peer,e);
+                    continue
+                }
+            },
+        };
+        session.last.store(start.elapsed().as_millis() as u64,Ordering::Relaxed);
+        if let Err(e) = session.upstream.send(&buf[..n]).await{
+            wd_log::log_debug_ln!("udp service port[{}] client[{}] send error:{}",port,peer,e);
+        }
+    }
+}
+
+async fn resolve(backend:&str)->anyhow::Result<SocketAddr>{
+    tokio::net::lookup_host(backend).await?.next().ok_or_else(||anyhow::anyhow!("backend[{}] not found",backend))
+}
+
+//the backend is the resolved address
+async fn udp_session(backend:&str)->anyhow::Result<UdpSession>{
+    let addr:SocketAddr = backend.parse()?;
+    let bind:SocketAddr = if addr.is_ipv4() {([0,0,0,0],0).into()}else{([0u16;8],0).into()};
+    let upstream = UdpSocket::bind(bind).await?;
+    upstream.connect(addr).await?;
+    Ok(UdpSession{upstream,last:AtomicU64::new(0)})
+}
+
+//forward the backend datagrams to the client, until neither side sends in the session timeout
+async fn udp_reply(socket:Arc<UdpSocket>,peer:SocketAddr,session:Arc<UdpSession>,sessions:Arc<Mutex<HashMap<SocketAddr,Arc<UdpSession>>>>,start:Instant,timeout:Duration){
+    let mut buf = vec![0u8;65535];
+    loop {
+        match tokio::time::timeout(timeout,session.upstream.recv(&mut buf)).await {
+            Ok(Ok(n)) => {
+                if let Err(e) = socket.send_to(&buf[..n],peer).await{
+                    wd_log::log_debug_ln!("udp service client[{}] reply error:{}",peer,e);
+                }
+            }
+            Ok(Err(e)) => {
+                wd_log::log_debug_ln!("udp service client[{}] backend recv error:{}",peer,e);
+                break
+            }
+            Err(_) => {
+                let idle = (start.elapsed().as_millis() as u64).saturating_sub(session.last.load(Ordering::Relaxed));
+                if idle >= timeout.as_millis() as u64 {
+                    break
+                }
+            }
+        }
+    }
+    sessions.lock().unwrap().remove(&peer);
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream, UdpSocket};
+    use wd_tools::sync::Acl;
+    use crate::service::config::Config;
+    use crate::service::stream_proxy::{parse_stream_services, proxy_tcp, serve_udp};
+
+    #[test]
+    fn test_parse_stream_services(){
+        let mut data = BTreeMap::new();
+        data.insert("3306".to_string(),"db/mysql:3306".to_string());
+        data.insert("1883".to_string()," mq/emqx:1883 ".to_string());
+        data.insert("abc".to_string(),"db/mysql:3306".to_string());
+        data.insert("5432".to_string(),"postgres:5432".to_string());
+        let (services,invalid) = parse_stream_services(&data);
+        assert_eq!(services.get(&3306).unwrap(),"mysql.db:3306");
+        assert_eq!(services.get(&1883).unwrap(),"emqx.mq:1883");
+        assert_eq!(services.len(),2);
+        assert_eq!(invalid.len(),2);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_tcp(){
+        let backend = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend_addr = backend.local_addr().unwrap().to_string();
+        tokio::spawn(async move{
+            let (mut conn,_) = backend.accept().await.unwrap();
+            let mut buf = [0u8;4];
+            conn.read_exact(&mut buf).await.unwrap();
+            conn.write_all(&buf).await.unwrap();
+        });
+        let gateway = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let gateway_addr = gateway.local_addr().unwrap();
+        tokio::spawn(async move{
+            let (conn,_) = gateway.accept().await.unwrap();
+            let _ = proxy_tcp(conn,backend_addr.as_str(),None).await;
+        });
+        let mut client = TcpStream::connect(gateway_addr).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8;4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf,b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_udp_max_sessions(){
+        let backend = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let backend_addr = backend.local_addr().unwrap().to_string();
+        tokio::spawn(async move{
+            let mut buf = [0u8;64];
+            while let Ok((n,peer)) = backend.recv_from(&mut buf).await{
+                let _ = backend.send_to(&buf[..n],peer).await;
+            }
+        });
+        let port = UdpSocket::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let cfg = Config{udp_max_sessions:1,..Default::default()};
+        tokio::spawn(serve_udp(port,Acl::new(backend_addr),Acl::new(cfg)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut buf = [0u8;64];
+        let first = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        first.send_to(b"ping",("127.0.0.1",port)).await.unwrap();
+        let n = tokio::time::timeout(Duration::from_secs(3),first.recv(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf[..n],b"ping");
+        //the second client is over the limit
+        let second = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        second.send_to(b"ping",("127.0.0.1",port)).await.unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(300),second.recv(&mut buf)).await.is_err());
+    }
+}
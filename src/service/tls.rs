@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use pingora::listeners::TlsAccept;
+use pingora::tls::ext;
+use pingora::tls::ssl::{NameType, SslRef};
+use wd_tools::sync::Acl;
+use crate::pkg::secret::SecretStore;
+use crate::service::config::Config;
+use crate::service::http_proxy::Router;
+
+//select the certificate of the https listener by the sni of client hello
+pub struct CertResolver{
+    router:Acl<HashMap<String,Router>>,
+    secrets:SecretStore,
+    cfg:Acl<Config>,
+}
+
+impl CertResolver{
+    pub fn new(router:Acl<HashMap<String,Router>>,secrets:SecretStore,cfg:Acl<Config>)->Self{
+        Self{router,secrets,cfg}
+    }
+}
+
+//the same as the router matching, the parent domain is tried when the host is not found
+pub fn find_by_host<T,F:Fn(&Router)->Option<T>>(routers:&HashMap<String,Router>,mut host:&str,f:F)->Option<T>{
+    loop {
+        if let Some(s) = routers.get(host).and_then(&f) {
+            return Some(s)
+        }
+        host = host.split_once('.')?.1;
+    }
+}
+
+//the routers of the exact host, then the wildcard hosts like `*.test.com` from the nearest parent.
+//a wildcard host does not match its parent domain itself
+pub fn host_routers<'a>(routers:&'a HashMap<String,Router>,host:&'a str)->impl Iterator<Item=&'a Router>{
+    let wildcards = host.match_indices('.').map(|(i,_)|format!("*{}",&host[i..]));
+    std::iter::once(host.to_string()).chain(wildcards).filter_map(|x|routers.get(x.as_str()))
+}
+
+pub fn find_exact_host<T,F:Fn(&Router)->Option<T>>(routers:&HashMap<String,Router>,host:&str,f:F)->Option<T>{
+    host_routers(routers,host).find_map(f)
+}
+
+fn find_tls_secret(routers:&HashMap<String,Router>,host:&str)->Option<String>{
+    let secret = |r:&Router|Some(r.tls_secret.clone()).filter(|x|!x.is_empty());
+    find_exact_host(routers,host,secret).or_else(||find_by_host(routers,host,secret))
+}
+
+#[async_trait::async_trait]
+impl TlsAccept for CertResolver{
+    async fn certificate_callback(&self, ssl: &mut SslRef) -> () {
+        //the default certificate is used without sni or the certificate of host
+        let host = ssl.servername(NameType::HOST_NAME).map(|x|x.to_string()).unwrap_or_default();
+        let key = find_tls_secret(&self.router.share(),host.as_str())
+            .or_else(||Some(self.cfg.share().default_certificate.clone()).filter(|x|!x.is_empty()));
+        let key = if let Some(s) = key{ s }else{
+            wd_log::log_debug_ln!("tls host[{}] has no certificate",host);
+            return
+        };
+        let cert = if let Some(s) = self.secrets.get(key.as_str()).and_then(|x|x.tls()){ s }else{
+            wd_log::log_warn_ln!("tls host[{}] secret[{}] not found or invalid",host,key);
+            return
+        };
+        let result = ext::ssl_use_certificate(ssl,&cert.chain[0])
+            .and_then(|_|ext::ssl_use_private_key(ssl,&cert.key))
+            .and_then(|_|cert.chain[1..].iter().try_for_each(|x|ext::ssl_add_chain_cert(ssl,x)));
+        if let Err(e) = result {
+            wd_log::log_error_ln!("tls host[{}] use certificate[{}] failed:{}",host,key,e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test{
+    use std::collections::HashMap;
+    use crate::service::http_proxy::Router;
+    use crate::service::tls::find_tls_secret;
+
+    #[test]
+    fn test_find_tls_secret(){
+        let mut routers = HashMap::new();
+        let mut r = Router::from_host("test.com");
+        r.tls_secret = "qa/test-tls".into();
+        routers.insert("test.com".to_string(),r);
+        routers.insert("api.test.com".to_string(),Router::from_host("api.test.com"));
+        assert_eq!(find_tls_secret(&routers,"test.com"),Some("qa/test-tls".into()));
+        assert_eq!(find_tls_secret(&routers,"api.test.com"),Some("qa/test-tls".into()));
+        assert_eq!(find_tls_secret(&routers,"www.test.com"),Some("qa/test-tls".into()));
+        assert_eq!(find_tls_secret(&routers,"other.com"),None);
+    }
+}
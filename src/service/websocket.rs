@@ -0,0 +1,170 @@
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{BorrowedFd, RawFd};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use prometheus::{IntGaugeVec, register_int_gauge_vec};
+use pingora::prelude::*;
+use pingora::upstreams::peer::PeerOptions;
+use tokio::task::JoinHandle;
+use crate::service::config::Config;
+use crate::service::http_proxy::RouterNode;
+
+fn websocket_active()->&'static IntGaugeVec{
+    static WEBSOCKET_ACTIVE:OnceLock<IntGaugeVec> = OnceLock::new();
+    WEBSOCKET_ACTIVE.get_or_init(||{
+        register_int_gauge_vec!("pingora_ingress_websocket_active_sessions","the websocket sessions being proxied",&["host"]).unwrap()
+    })
+}
+
+//an upgraded connection, the active gauge is decreased when the request ctx is dropped
+pub struct WebsocketSession{
+    host:String,
+    start:Instant,
+    max_duration:Option<Duration>,
+    //shut the upstream connection down at the max duration
+    timer:Option<JoinHandle<()>>,
+}
+
+impl WebsocketSession{
+    //conn is the duplicated upstream socket, see upstream_conn
+    pub fn new(node:&RouterNode,conn:Option<TcpStream>)->Self{
+        websocket_active().with_label_values(&[node.host.as_str()]).inc();
+        let max_duration = node.policy.websocket.max_duration.filter(|x|*x > 0).map(Duration::from_secs);
+        let timer = max_duration.zip(conn).map(|(max,conn)|{
+            let host = node.host.clone();
+            tokio::spawn(async move{
+                tokio::time::sleep(max).await;
+                wd_log::log_debug_ln!("websocket session of host[{}] over {:?}, shutdown",host,max);
+                let _ = conn.shutdown(Shutdown::Both);
+            })
+        });
+        Self{
+            host:node.host.clone(),
+            start:Instant::now(),
+            max_duration,
+            timer,
+        }
+    }
+    //the upstream data is checked, the session without a timer is closed by the idle timeout
+    pub fn check_duration(&self)->Result<()>{
+        match self.max_duration {
+            Some(max) if self.start.elapsed() > max => {
+                Error::e_explain(ErrorType::new("WebsocketMaxDuration"),format!("websocket session over {:?}",max))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for WebsocketSession{
+    fn drop(&mut self) {
+        //the duplicated socket is closed with the timer
+        if let Some(ref t) = self.timer {
+            t.abort();
+        }
+        websocket_active().with_label_values(&[self.host.as_str()]).dec();
+    }
+}
+
+//pingora 0.1 has no hook on the client data of an upgraded connection, so the max duration shuts the upstream socket down.
+//the socket is duplicated, the timer never touches a descriptor reused after pingora closes the connection
+pub fn upstream_conn(fd:RawFd)->Option<TcpStream>{
+    //SAFETY: the fd of the upstream connection is open during connected_to_upstream
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    match fd.try_clone_to_owned() {
+        Ok(o) => Some(TcpStream::from(o)),
+        Err(e) => {
+            wd_log::log_warn_ln!("websocket duplicate the upstream socket failed:{}",e);
+            None
+        }
+    }
+}
+
+//the upgraded connection uses the websocket idle timeout instead of the proxy timeouts
+pub fn set_websocket_peer_options(node:&RouterNode,cfg:&Config,opt:&mut PeerOptions){
+    let ws = &node.policy.websocket;
+    let idle = ws.idle_timeout.unwrap_or(cfg.websocket_idle_timeout);
+    let timeout = match (idle,ws.max_duration.unwrap_or(0)) {
+        (0,0) => None,
+        (0,max) => Some(max),
+        (idle,0) => Some(idle),
+        (idle,max) => Some(idle.min(max)),
+    }.map(Duration::from_secs);
+    opt.read_timeout = timeout;
+    opt.write_timeout = timeout;
+    opt.idle_timeout = timeout;
+}
+
+#[cfg(test)]
+mod test{
+    use std::time::{Duration, Instant};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use crate::pkg::ingress::IngRule;
+    use crate::service::config::Config;
+    use crate::service::http_proxy::RouterNode;
+    use crate::service::http_proxy::test::{connect, read_head, start_proxy};
+    use crate::service::websocket::{websocket_active, WebsocketSession};
+
+    //the gauge is global, every test has its own host label
+    #[tokio::test]
+    async fn test_websocket_session(){
+        let mut rule = IngRule{path:"/ws".into(),ty:1,backend:"echo".into(),port:80,..Default::default()};
+        rule.policy.websocket.max_duration = Some(0);
+        let node = RouterNode::from(rule).set_host("ws-session.test.com");
+        let a = WebsocketSession::new(&node,None);
+        let b = WebsocketSession::new(&node,None);
+        assert_eq!(websocket_active().with_label_values(&["ws-session.test.com"]).get(),2);
+        assert!(a.check_duration().is_ok());
+        drop(a);
+        drop(b);
+        assert_eq!(websocket_active().with_label_values(&["ws-session.test.com"]).get(),0);
+    }
+
+    //the upgrade is proxied, the client data only session is closed at the max duration
+    #[tokio::test]
+    async fn test_websocket_proxy_upgrade(){
+        let upstream = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move{
+            let (mut stream,_) = upstream.accept().await.unwrap();
+            let head = read_head(&mut stream).await;
+            assert!(head.contains("upgrade: websocket"));
+            stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n").await.unwrap();
+            //echo the first frame, then read the client data silently
+            let mut buf = [0u8;64];
+            let n = stream.read(&mut buf).await.unwrap();
+            stream.write_all(&buf[..n]).await.unwrap();
+            while stream.read(&mut buf).await.unwrap_or(0) > 0 {}
+        });
+
+        let cfg = Config{websocket_idle_timeout:60,..Default::default()};
+        let mut rule = IngRule{path:"/ws".into(),ty:1,backend:"127.0.0.1".into(),port:port as i32,..Default::default()};
+        rule.policy.websocket.max_duration = Some(2);
+        let addr = start_proxy(cfg,"ws-proxy.test.com",vec![rule]).await;
+
+        let mut client = connect(addr).await;
+        client.write_all(b"GET /ws HTTP/1.1\r\nHost: ws-proxy.test.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n").await.unwrap();
+        let head = read_head(&mut client).await;
+        assert!(head.starts_with("http/1.1 101"),"{}",head);
+        let start = Instant::now();
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8;4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf,b"ping");
+        assert_eq!(websocket_active().with_label_values(&["ws-proxy.test.com"]).get(),1);
+
+        //only the client sends data from now on
+        let closed = tokio::time::timeout(Duration::from_secs(10),async{
+            loop {
+                tokio::select! {
+                    n = client.read(&mut buf) => if n.unwrap_or(0) == 0 { break },
+                    _ = tokio::time::sleep(Duration::from_millis(200)) => {
+                        if client.write_all(b"data").await.is_err() { break }
+                    }
+                }
+            }
+        }).await;
+        assert!(closed.is_ok());
+        assert!(start.elapsed() >= Duration::from_secs(2));
+    }
+}